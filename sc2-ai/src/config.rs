@@ -0,0 +1,124 @@
+//! Options which may be given either on the command line or in a TOML configuration file. Options
+//! given on the command line take precedence.
+
+use std::path::Path;
+
+use clap::Args;
+use serde::Deserialize;
+
+use crate::core::{AiBuild, Difficulty, GameSetup, OpponentType, Race};
+
+#[derive(Args, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Map to create the game on. Not needed when the game is created by a ladder server.
+    #[arg(short, long)]
+    pub map: Option<String>,
+
+    /// Our race.
+    #[arg(long)]
+    pub race: Option<Race>,
+
+    /// Our player name.
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Who to play against. A participant opponent is another instance of this bot, as is an
+    /// observer, which watches the game without an opponent taking part.
+    #[arg(long)]
+    pub opponent: Option<OpponentType>,
+
+    #[arg(long = "opponent-race")]
+    pub opponent_race: Option<Race>,
+
+    /// Difficulty of a computer opponent.
+    #[arg(long)]
+    pub difficulty: Option<Difficulty>,
+
+    /// Strategy of a computer opponent.
+    #[arg(long = "ai-build")]
+    pub ai_build: Option<AiBuild>,
+
+    /// Seed for the game's random number generator.
+    #[arg(long)]
+    pub seed: Option<u32>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Fill in any options not already set from `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            map: self.map.or(other.map),
+            race: self.race.or(other.race),
+            name: self.name.or(other.name),
+            opponent: self.opponent.or(other.opponent),
+            opponent_race: self.opponent_race.or(other.opponent_race),
+            difficulty: self.difficulty.or(other.difficulty),
+            ai_build: self.ai_build.or(other.ai_build),
+            seed: self.seed.or(other.seed),
+        }
+    }
+
+    pub fn game_setup(&self) -> GameSetup {
+        let default = GameSetup::default();
+
+        GameSetup {
+            race: self.race.unwrap_or(default.race),
+            name: self.name.clone().unwrap_or(default.name),
+            opponent: self.opponent.unwrap_or(default.opponent),
+            opponent_race: self.opponent_race.unwrap_or(default.opponent_race),
+            difficulty: self.difficulty.unwrap_or(default.difficulty),
+            ai_build: self.ai_build.unwrap_or(default.ai_build),
+            seed: self.seed.or(default.seed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_takes_precedence_over_file() {
+        let args = Config {
+            race: Some(Race::Protoss),
+            seed: Some(1),
+            ..Default::default()
+        };
+        let file: Config = toml::from_str(
+            r#"
+            map = "Flat"
+            race = "terran"
+            opponent = "observer"
+            seed = 2
+            "#,
+        )
+        .unwrap();
+
+        let config = args.or(file);
+        assert_eq!(config.map.as_deref(), Some("Flat"));
+        assert_eq!(config.race, Some(Race::Protoss));
+        assert_eq!(config.opponent, Some(OpponentType::Observer));
+        assert_eq!(config.seed, Some(1));
+
+        let setup = config.game_setup();
+        assert_eq!(setup.race, Race::Protoss);
+        assert_eq!(setup.difficulty, GameSetup::default().difficulty);
+    }
+
+    #[test]
+    fn observer_opponent_has_no_race() {
+        let setup = GameSetup {
+            opponent: OpponentType::Observer,
+            ..Default::default()
+        };
+
+        let opponent = setup.opponent();
+        assert_eq!(opponent.type_(), sc2_proto::sc2api::PlayerType::Observer);
+        assert!(opponent.race.is_none());
+    }
+}
@@ -1,38 +1,397 @@
-use std::net::TcpStream;
+use std::{
+    collections::VecDeque,
+    net::TcpStream,
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+    time::{Duration, Instant},
+};
 
-use anyhow::anyhow;
-use bevy::ecs::system::Resource;
-use protobuf::Message as _;
-use sc2_proto::sc2api::{InterfaceOptions, PlayerSetup, Request, Response, response_create_game};
-use thiserror::Error;
+use bevy::{ecs::system::Resource, utils::synccell::SyncCell};
+use protobuf::{Message as _, MessageField};
+use sc2_proto::sc2api::{
+    InterfaceOptions, PlayerSetup, PlayerType, PortSet, Request, Response, Status,
+};
+use tracing::{error, info, warn};
 use tungstenite::{Message, WebSocket, stream::MaybeTlsStream};
 
+use super::{
+    error::CoreError,
+    interface::InterfaceConfig,
+    record::{Playback, Recorder},
+    trace::{RequestKind, TraceConfig, Tracer},
+};
+
+/// Connection to the game API.
+///
+/// The socket is served by a background thread, so requests may be pipelined. Requests sent with
+/// [`Client::submit`] or [`Client::prefetch`] are answered while the app carries on, with
+/// responses received in order by the next [`Client::send`]. A prefetched response is kept until
+/// the same request is sent, or until a request which changes the game, such as a step, makes it
+/// out of date.
+///
+/// Every request/response pair may optionally be recorded to a file. A recording can then be used
+/// in place of the game through [`Client::playback`]. They may also be traced in a readable form
+/// through [`Client::trace_to`]. Failing to write either is logged and stops it, rather than
+/// failing the request.
 #[derive(Resource, Debug)]
-pub struct Client(WebSocket<MaybeTlsStream<TcpStream>>);
+pub struct Client {
+    transport: Transport,
+    in_flight: VecDeque<InFlight>,
+    prefetched: Option<(Request, Response)>,
+    recorder: Option<Recorder>,
+    tracer: Option<Tracer>,
+    timings: Vec<(RequestKind, Duration)>,
+    game_loop: u32,
+    status: Status,
+}
+
+/// Ports used by the game instances of a multiplayer game to communicate with each other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GamePorts {
+    shared: i32,
+    server: PortSet,
+    clients: Vec<PortSet>,
+}
+
+impl GamePorts {
+    /// Number of ports, including `start_port`, reserved by [`GamePorts::from_start_port`].
+    pub const fn range_len(clients: usize) -> u16 {
+        4 + 2 * clients as u16
+    }
+
+    /// Allocate ports consecutively following `start_port`, as expected by ladder servers.
+    pub fn from_start_port(start_port: u16, clients: usize) -> Self {
+        let start_port = i32::from(start_port);
+        let port_set = |offset: i32| PortSet {
+            game_port: Some(start_port + offset),
+            base_port: Some(start_port + offset + 1),
+            ..Default::default()
+        };
+
+        Self {
+            shared: start_port + 1,
+            server: port_set(2),
+            clients: (0..clients as i32).map(|i| port_set(4 + 2 * i)).collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Transport {
+    Socket(Connection),
+    Playback {
+        playback: Playback,
+        responses: VecDeque<Result<(Response, Duration), CoreError>>,
+    },
+}
+
+impl Transport {
+    fn write(&mut self, request: &Request) -> Result<(), CoreError> {
+        match self {
+            Transport::Socket(connection) => connection.write(request),
+            Transport::Playback {
+                playback,
+                responses,
+            } => {
+                let start = Instant::now();
+                let response = playback.respond(request);
+                responses.push_back(response.map(|response| (response, start.elapsed())));
+                Ok(())
+            }
+        }
+    }
+
+    /// Read the response to the oldest request written, with the time taken to answer it.
+    fn read(&mut self) -> Result<(Response, Duration), CoreError> {
+        match self {
+            Transport::Socket(connection) => connection.read(),
+            // Reads only follow writes, each of which queues a response, so an empty queue means
+            // the recording doesn't line up with the requests.
+            Transport::Playback {
+                playback,
+                responses,
+            } => responses
+                .pop_front()
+                .unwrap_or(Err(CoreError::RecordingEnded(playback.entries()))),
+        }
+    }
+}
+
+/// Socket to the game, served by a background thread which writes each request then reads and
+/// decodes its response.
+///
+/// Requests are timed on the thread, from writing the request to decoding the response, so time
+/// spent queued behind other requests or waiting for the app to read the response isn't counted.
+#[derive(Debug)]
+struct Connection {
+    requests: Sender<Vec<u8>>,
+    responses: SyncCell<Receiver<Result<(Response, Duration), CoreError>>>,
+}
+
+impl Connection {
+    fn spawn(mut socket: WebSocket<MaybeTlsStream<TcpStream>>) -> Self {
+        let (requests, request_rx) = mpsc::channel::<Vec<u8>>();
+        let (response_tx, responses) = mpsc::channel();
+
+        // Exits once the client is dropped, closing the socket with it.
+        std::thread::spawn(move || {
+            for request in request_rx {
+                let start = Instant::now();
+                let response = socket
+                    .send(Message::binary(request))
+                    .and_then(|_| socket.read())
+                    .map_err(CoreError::from)
+                    .and_then(|msg| {
+                        let mut response = Response::new();
+                        response.merge_from_bytes(&msg.into_data())?;
+                        Ok((response, start.elapsed()))
+                    });
+
+                if response_tx.send(response).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests,
+            responses: SyncCell::new(responses),
+        }
+    }
+
+    fn write(&mut self, request: &Request) -> Result<(), CoreError> {
+        self.requests
+            .send(request.write_to_bytes()?)
+            .map_err(|_| tungstenite::Error::AlreadyClosed.into())
+    }
+
+    fn read(&mut self) -> Result<(Response, Duration), CoreError> {
+        self.responses
+            .get()
+            .recv()
+            .unwrap_or(Err(tungstenite::Error::AlreadyClosed.into()))
+    }
+}
+
+/// A request which has been sent but whose response hasn't yet been received.
+#[derive(Debug)]
+struct InFlight {
+    request: Request,
+    expected: Expected,
+}
 
-#[derive(Error, Debug, Clone)]
-#[error("{error:?}: {detail}")]
-pub struct ApiError {
-    error: response_create_game::Error,
-    detail: String,
+/// What's done with the response to a request in flight once it's received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Expected {
+    /// Returned by, or checked for errors by, the next request sent.
+    Response,
+    /// Kept for the next identical request.
+    Prefetch,
+    /// Discarded, as the game has changed since it was prefetched.
+    Stale,
 }
 
 impl Client {
-    pub fn connect(host: &str, port: i32) -> Result<Self, anyhow::Error> {
+    const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+    const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+    /// Connect to the game API, retrying with exponential backoff until `timeout` has elapsed.
+    pub fn connect(host: &str, port: i32, timeout: Duration) -> Result<Self, CoreError> {
+        let url = format!("ws://{}:{}/sc2api", host, port);
+        let start = Instant::now();
+        let mut backoff = Self::INITIAL_BACKOFF;
+
         let (ws, _rs) = loop {
-            if let Ok(result) = tungstenite::connect(format!("ws://{}:{}/sc2api", host, port)) {
-                break result;
+            let error = match tungstenite::connect(&url) {
+                Ok(result) => break result,
+                Err(e) => e,
+            };
+
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(CoreError::ConnectTimeout { url, timeout });
             }
+
+            info!(
+                "Waiting for game at {url} ({:.1?} elapsed): {error}",
+                elapsed
+            );
+            std::thread::sleep(backoff.min(timeout - elapsed));
+            backoff = (backoff * 2).min(Self::MAX_BACKOFF);
         };
-        Ok(Self(ws))
+
+        info!("Connected to game at {url}");
+        Ok(Self::new(Transport::Socket(Connection::spawn(ws))))
+    }
+
+    /// Create a client which answers requests from a recording made with
+    /// [`Client::record_to`].
+    pub fn playback(path: &Path) -> Result<Self, CoreError> {
+        Ok(Self::new(Transport::Playback {
+            playback: Playback::open(path)?,
+            responses: VecDeque::new(),
+        }))
+    }
+
+    fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            in_flight: VecDeque::new(),
+            prefetched: None,
+            recorder: None,
+            tracer: None,
+            timings: Vec::new(),
+            game_loop: 0,
+            status: Status::launched,
+        }
+    }
+
+    /// Record every following request/response pair to a file.
+    pub fn record_to(&mut self, path: &Path) -> Result<(), CoreError> {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    /// Trace every following request and response to a file.
+    pub fn trace_to(&mut self, config: TraceConfig) -> Result<(), CoreError> {
+        self.tracer = Some(Tracer::create(config)?);
+        Ok(())
+    }
+
+    /// Take the time taken by each request answered since the last call, from the request being
+    /// written to the game to its response being decoded.
+    pub fn take_timings(&mut self) -> Vec<(RequestKind, Duration)> {
+        std::mem::take(&mut self.timings)
+    }
+
+    /// Status of the game as of the last response received, not counting prefetched responses
+    /// until they're used.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Send a request and wait for the response.
+    ///
+    /// Errors reported in the response are returned as [`CoreError::Api`], or
+    /// [`CoreError::GameEnded`] if the game is no longer running. Errors in the responses to any
+    /// requests submitted beforehand are returned first.
+    pub fn send(&mut self, request: Request) -> Result<Response, CoreError> {
+        // Responses arrive in order, so those to earlier requests must be received first.
+        self.wait()?;
+
+        match self.prefetched.take() {
+            Some((prefetched, response)) if prefetched == request => self.accept(response),
+            _ => {
+                self.write(request, Expected::Response)?;
+                let (_, _, response) = self.receive()?;
+                self.accept(response)
+            }
+        }
+    }
+
+    /// Wait for the responses to every request in flight, returning the first error among those
+    /// submitted. Prefetched responses are kept.
+    pub fn wait(&mut self) -> Result<(), CoreError> {
+        while !self.in_flight.is_empty() {
+            let (sent, expected, response) = self.receive()?;
+            match expected {
+                Expected::Response => {
+                    self.accept(response)?;
+                }
+                Expected::Prefetch => self.prefetched = Some((sent, response)),
+                Expected::Stale => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a request without waiting for the response. Any error in the response is returned by
+    /// the following [`Client::send`].
+    pub fn submit(&mut self, request: Request) -> Result<(), CoreError> {
+        self.write(request, Expected::Response)
+    }
+
+    /// Send a request ahead of time. If the same request is sent later, it's answered with the
+    /// response to this one rather than being sent again. The response is discarded if a request
+    /// which changes the game is sent first.
+    pub fn prefetch(&mut self, request: Request) -> Result<(), CoreError> {
+        self.write(request, Expected::Prefetch)
+    }
+
+    fn write(&mut self, request: Request, expected: Expected) -> Result<(), CoreError> {
+        if changes_game(&request) {
+            self.prefetched = None;
+            for in_flight in &mut self.in_flight {
+                if in_flight.expected == Expected::Prefetch {
+                    in_flight.expected = Expected::Stale;
+                }
+            }
+        }
+
+        // Traced before sending so the request is seen even if the game never responds.
+        let traced = self
+            .tracer
+            .as_mut()
+            .map_or(Ok(()), |tracer| tracer.request(self.game_loop, &request));
+        if let Err(e) = traced {
+            error!("Failed to write trace, tracing stopped: {e}");
+            self.tracer = None;
+        }
+
+        self.transport.write(&request)?;
+        self.in_flight.push_back(InFlight { request, expected });
+        Ok(())
+    }
+
+    /// Receive the response to the oldest request in flight.
+    ///
+    /// Responses are timed, recorded and traced in the order they arrive, but not otherwise looked
+    /// at until passed to [`Client::accept`].
+    fn receive(&mut self) -> Result<(Request, Expected, Response), CoreError> {
+        let InFlight { request, expected } = self
+            .in_flight
+            .pop_front()
+            .expect("A request should be in flight");
+
+        let (response, elapsed) = self.transport.read()?;
+
+        if let Some(request) = &request.request {
+            self.timings.push((RequestKind::from(request), elapsed));
+        }
+
+        let recorded = self.recorder.as_mut().map_or(Ok(()), |recorder| {
+            recorder.record(self.game_loop, &request, &response)
+        });
+        if let Err(e) = recorded {
+            error!("Failed to write recording, recording stopped: {e}");
+            self.recorder = None;
+        }
+        let traced = self.tracer.as_mut().map_or(Ok(()), |tracer| {
+            tracer.response(self.game_loop, &request, &response)
+        });
+        if let Err(e) = traced {
+            error!("Failed to write trace, tracing stopped: {e}");
+            self.tracer = None;
+        }
+
+        Ok((request, expected, response))
     }
 
-    pub fn send(&mut self, request: Request) -> Result<Response, anyhow::Error> {
-        self.0.send(Message::binary(request.write_to_bytes()?))?;
+    /// Update the state of the game from a response, then check it for errors.
+    fn accept(&mut self, mut response: Response) -> Result<Response, CoreError> {
+        self.status = response.status();
+        if response.has_observation() {
+            self.game_loop = response.observation().observation.game_loop();
+        } else if response.has_step() {
+            self.game_loop = response.step().simulation_loop();
+        }
 
-        let msg = self.0.read()?;
-        let mut response = Response::new();
-        response.merge_from_bytes(&msg.into_data())?;
+        if !response.error.is_empty() {
+            return Err(match response.status() {
+                Status::ended | Status::quit => CoreError::GameEnded,
+                _ => CoreError::Api(std::mem::take(&mut response.error)),
+            });
+        }
 
         Ok(response)
     }
@@ -43,7 +402,8 @@ impl Client {
         player: PlayerSetup,
         opponent: PlayerSetup,
         realtime: bool,
-    ) -> Result<(), anyhow::Error> {
+        seed: Option<u32>,
+    ) -> Result<(), CoreError> {
         let request = {
             let mut request = Request::new();
             let req_create_game = request.mut_create_game();
@@ -51,6 +411,7 @@ impl Client {
             req_create_game.mut_local_map().set_map_path(map);
             req_create_game.player_setup = vec![player, opponent];
             req_create_game.set_realtime(realtime);
+            req_create_game.random_seed = seed;
             request
         };
 
@@ -58,46 +419,189 @@ impl Client {
         {
             let res_create_game = response.create_game();
             if res_create_game.has_error() {
-                return Err(ApiError {
+                return Err(CoreError::CreateGame {
                     error: res_create_game.error(),
                     detail: res_create_game.error_details().to_owned(),
-                }
-                .into());
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start observing a replay from the perspective of `observed_player`.
+    pub fn start_replay(
+        &mut self,
+        path: &Path,
+        observed_player: u32,
+        realtime: bool,
+        interface: InterfaceConfig,
+    ) -> Result<(), CoreError> {
+        // The game resolves relative paths against its own working directory.
+        let path = std::fs::canonicalize(path)?;
+
+        let request = {
+            let mut request = Request::new();
+            let req_start_replay = request.mut_start_replay();
+
+            req_start_replay.set_replay_path(path.to_string_lossy().into_owned());
+            req_start_replay.set_observed_player_id(observed_player as i32);
+            req_start_replay.set_realtime(realtime);
+            req_start_replay.set_disable_fog(interface.disable_fog);
+            req_start_replay.options = MessageField::some(InterfaceOptions::from(interface));
+            request
+        };
+
+        let response = self.send(request)?;
+        {
+            let res_start_replay = response.start_replay();
+            if res_start_replay.has_error() {
+                return Err(CoreError::StartReplay {
+                    error: res_start_replay.error(),
+                    detail: res_start_replay.error_details().to_owned(),
+                });
             }
         }
 
         Ok(())
     }
 
-    pub fn join_game(&mut self, player: PlayerSetup) -> Result<u32, anyhow::Error> {
+    /// Join a game as `player`. Multiplayer games also require the ports each game instance uses.
+    ///
+    /// An observer follows the first player the game was created with, as player IDs are given
+    /// out in the order of the player setups.
+    pub fn join_game(
+        &mut self,
+        player: PlayerSetup,
+        ports: Option<&GamePorts>,
+        interface: InterfaceConfig,
+    ) -> Result<u32, CoreError> {
         let mut request = Request::new();
 
         let game = request.mut_join_game();
-        game.set_race(player.race());
+        if player.type_() == PlayerType::Observer {
+            game.set_observed_player_id(1);
+        } else {
+            game.set_race(player.race());
+        }
         *game.mut_player_name() = player.player_name().to_owned();
 
-        game.options.0 = Some(Box::new(InterfaceOptions {
-            raw: Some(true),
-            score: Some(true),
-            show_cloaked: Some(true),
-            show_burrowed_shadows: Some(true),
-            show_placeholders: Some(true),
-            raw_affects_selection: Some(false),
-            raw_crop_to_playable_area: Some(false),
-            ..Default::default()
-        }));
+        if let Some(ports) = ports {
+            game.set_shared_port(ports.shared);
+            game.server_ports = MessageField::some(ports.server.clone());
+            game.client_ports = ports.clients.clone();
+        }
+
+        game.options = MessageField::some(InterfaceOptions::from(interface));
 
         let response = self.send(request)?;
         let response = response.join_game();
 
         if response.has_error() {
-            return Err(anyhow!(
-                "{:?}: {}",
-                response.error(),
-                response.error_details().to_owned(),
-            ));
+            return Err(CoreError::JoinGame {
+                error: response.error(),
+                detail: response.error_details().to_owned(),
+            });
         };
 
         Ok(response.player_id())
     }
+
+    /// Start a new game with the same setup as the last. Single player games only.
+    pub fn restart_game(&mut self) -> Result<(), CoreError> {
+        let mut request = Request::new();
+        request.mut_restart_game();
+
+        let response = self.send(request)?;
+        let response = response.restart_game();
+
+        if response.has_error() {
+            return Err(CoreError::RestartGame {
+                error: response.error(),
+                detail: response.error_details().to_owned(),
+            });
+        }
+
+        if response.need_hard_reset() {
+            warn!("Game requested a hard reset after restarting");
+        }
+
+        Ok(())
+    }
+
+    /// Leave a multiplayer game, surrendering if it's still in progress.
+    pub fn leave_game(&mut self) -> Result<(), CoreError> {
+        let mut request = Request::new();
+        request.mut_leave_game();
+
+        // The game may already have ended.
+        match self.send(request) {
+            Ok(_) | Err(CoreError::GameEnded) => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Ask the game to close. Nothing can be sent afterwards.
+    pub fn quit(&mut self) -> Result<(), CoreError> {
+        let mut request = Request::new();
+        request.mut_quit();
+
+        match self.send(request) {
+            Ok(_) | Err(CoreError::GameEnded) => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Save a replay of the current game to `path`.
+    pub fn save_replay(&mut self, path: &Path) -> Result<(), CoreError> {
+        let mut request = Request::new();
+        request.mut_save_replay();
+
+        let response = self.send(request)?;
+        std::fs::write(path, response.save_replay().data())?;
+        Ok(())
+    }
+}
+
+/// Whether a request changes the state of the game, so that responses prefetched beforehand are
+/// out of date.
+fn changes_game(request: &Request) -> bool {
+    use sc2_proto::sc2api::request::Request as ApiRequest;
+
+    matches!(
+        request.request,
+        Some(
+            ApiRequest::Step(_)
+                | ApiRequest::CreateGame(_)
+                | ApiRequest::JoinGame(_)
+                | ApiRequest::RestartGame(_)
+                | ApiRequest::StartReplay(_)
+                | ApiRequest::LeaveGame(_)
+                | ApiRequest::QuickLoad(_)
+                | ApiRequest::Quit(_)
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_ports_within_range() {
+        let ports = GamePorts::from_start_port(5000, 2);
+
+        let mut used = vec![ports.shared];
+        for set in std::iter::once(&ports.server).chain(&ports.clients) {
+            used.extend([set.game_port(), set.base_port()]);
+        }
+        assert_eq!(ports.clients.len(), 2);
+
+        let end = 5000 + i32::from(GamePorts::range_len(2));
+        assert!(used.iter().all(|port| (5001..end).contains(port)));
+
+        used.sort_unstable();
+        used.dedup();
+        assert_eq!(used.len(), 7);
+    }
 }
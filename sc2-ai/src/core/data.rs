@@ -0,0 +1,102 @@
+use bevy::{ecs::system::Resource, utils::HashMap};
+use num_traits::FromPrimitive as _;
+use tracing::debug;
+
+use sc2_proto::{
+    AbilityId,
+    data::{AbilityData, BuffData, EffectData, UnitTypeData, UpgradeData},
+    sc2api::ResponseData,
+    unit::TypeId,
+};
+
+/// Static data of every unit, ability, upgrade, buff and effect in the game.
+///
+/// This contains things like costs, supply, build times, weapons and movement speeds. It's fetched
+/// once at startup.
+#[derive(Resource, Default, Clone, Debug, PartialEq)]
+pub struct GameData {
+    units: HashMap<TypeId, UnitTypeData>,
+    abilities: HashMap<AbilityId, AbilityData>,
+    upgrades: HashMap<u32, UpgradeData>,
+    buffs: HashMap<u32, BuffData>,
+    effects: HashMap<u32, EffectData>,
+}
+
+impl From<ResponseData> for GameData {
+    fn from(value: ResponseData) -> Self {
+        // Data for IDs added to the game since the ID enums were generated is dropped.
+        let units = value
+            .units
+            .into_iter()
+            .filter_map(|unit| match TypeId::from_u32(unit.unit_id()) {
+                Some(id) => Some((id, unit)),
+                None => {
+                    debug!("Unknown unit type: {}", unit.unit_id());
+                    None
+                }
+            })
+            .collect();
+
+        let abilities = value
+            .abilities
+            .into_iter()
+            .filter_map(|ability| match AbilityId::from_u32(ability.ability_id()) {
+                Some(id) => Some((id, ability)),
+                None => {
+                    debug!("Unknown ability: {}", ability.ability_id());
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            units,
+            abilities,
+            upgrades: value
+                .upgrades
+                .into_iter()
+                .map(|upgrade| (upgrade.upgrade_id(), upgrade))
+                .collect(),
+            buffs: value
+                .buffs
+                .into_iter()
+                .map(|buff| (buff.buff_id(), buff))
+                .collect(),
+            effects: value
+                .effects
+                .into_iter()
+                .map(|effect| (effect.effect_id(), effect))
+                .collect(),
+        }
+    }
+}
+
+impl GameData {
+    pub fn unit(&self, id: TypeId) -> Option<&UnitTypeData> {
+        self.units.get(&id)
+    }
+
+    pub fn ability(&self, id: AbilityId) -> Option<&AbilityData> {
+        self.abilities.get(&id)
+    }
+
+    pub fn upgrade(&self, id: u32) -> Option<&UpgradeData> {
+        self.upgrades.get(&id)
+    }
+
+    pub fn buff(&self, id: u32) -> Option<&BuffData> {
+        self.buffs.get(&id)
+    }
+
+    pub fn effect(&self, id: u32) -> Option<&EffectData> {
+        self.effects.get(&id)
+    }
+
+    pub fn units(&self) -> impl Iterator<Item = (TypeId, &UnitTypeData)> {
+        self.units.iter().map(|(&id, unit)| (id, unit))
+    }
+
+    pub fn abilities(&self) -> impl Iterator<Item = (AbilityId, &AbilityData)> {
+        self.abilities.iter().map(|(&id, ability)| (id, ability))
+    }
+}
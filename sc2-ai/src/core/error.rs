@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use bevy::ecs::event::Event;
+use sc2_proto::sc2api::{
+    response_create_game, response_join_game, response_restart_game, response_start_replay,
+};
+use thiserror::Error;
+
+/// Errors arising from communicating with the game API.
+///
+/// Core systems report these as events rather than panicking. The app exits on any error, with an
+/// error code for anything other than [`CoreError::GameEnded`].
+#[derive(Event, Error, Debug)]
+pub enum CoreError {
+    #[error("Transport error: {0}")]
+    Transport(#[from] tungstenite::Error),
+
+    #[error("Protocol error: {0}")]
+    Protocol(#[from] protobuf::Error),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Timed out after {timeout:?} connecting to game at {url}")]
+    ConnectTimeout { url: String, timeout: Duration },
+
+    #[error("Failed to create game: {error:?}: {detail}")]
+    CreateGame {
+        error: response_create_game::Error,
+        detail: String,
+    },
+
+    #[error("Failed to join game: {error:?}: {detail}")]
+    JoinGame {
+        error: response_join_game::Error,
+        detail: String,
+    },
+
+    #[error("Failed to start replay: {error:?}: {detail}")]
+    StartReplay {
+        error: response_start_replay::Error,
+        detail: String,
+    },
+
+    #[error("Failed to restart game: {error:?}: {detail}")]
+    RestartGame {
+        error: response_restart_game::Error,
+        detail: String,
+    },
+
+    /// The API responded with an error status.
+    #[error("Request failed: {}", .0.join("; "))]
+    Api(Vec<String>),
+
+    /// The response didn't contain a field required by the request.
+    #[error("Response missing expected field: {0}")]
+    UnexpectedResponse(&'static str),
+
+    /// The game didn't return a result for every query sent, or returned too many.
+    #[error("Expected {expected} query results, received {received}")]
+    QueryResults { expected: usize, received: usize },
+
+    /// A request was rejected because the game has already ended.
+    #[error("Game has ended")]
+    GameEnded,
+
+    #[error("Recording ended after {0} entries")]
+    RecordingEnded(usize),
+
+    #[error("Requests diverged from recording at entry {entry} (game loop {game_loop})")]
+    RecordingDiverged { entry: usize, game_loop: u32 },
+}
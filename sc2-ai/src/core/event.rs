@@ -0,0 +1,25 @@
+//! Events read from each observation.
+
+use bevy::ecs::event::Event;
+
+use sc2_proto::sc2api;
+
+/// A chat message sent by another player.
+#[derive(Event, Clone, Debug, PartialEq, Eq)]
+pub struct ChatReceived {
+    pub player_id: u32,
+    pub message: String,
+}
+
+impl From<sc2api::ChatReceived> for ChatReceived {
+    fn from(value: sc2api::ChatReceived) -> Self {
+        Self {
+            player_id: value.player_id(),
+            message: value.message.unwrap_or_default(),
+        }
+    }
+}
+
+/// An alert shown to the player, such as a nuke being launched or research completing.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlertEvent(pub sc2api::Alert);
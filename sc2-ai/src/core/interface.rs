@@ -0,0 +1,255 @@
+use bevy::ecs::system::Resource;
+use protobuf::MessageField;
+
+use sc2_proto::{
+    common::{ImageData, Point2D, PointI, RectangleI, Size2DI},
+    raw::StartRaw,
+    sc2api::{InterfaceOptions, SpatialCameraSetup},
+};
+
+/// Options controlling what the game includes in observations.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct InterfaceConfig {
+    pub show_cloaked: bool,
+    pub show_burrowed_shadows: bool,
+    pub show_placeholders: bool,
+    pub raw_affects_selection: bool,
+
+    /// Give coordinates relative to the playable area of the map rather than the whole map. The
+    /// map's grids are cropped to match.
+    pub crop_to_playable_area: bool,
+
+    /// Observe the whole map, ignoring fog of war. This is cheating so is only for debugging.
+    pub disable_fog: bool,
+
+    /// Also observe the screen and minimap as feature layers. Only raw data is observed if unset.
+    pub feature_layers: Option<FeatureLayerConfig>,
+}
+
+/// Resolution of the feature layers rendered by the game.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeatureLayerConfig {
+    /// Width and height of the screen layers in pixels.
+    pub screen: (u32, u32),
+
+    /// Width and height of the minimap layers in pixels.
+    pub minimap: (u32, u32),
+
+    /// Width of the area of the map shown by the screen, in game units.
+    pub camera_width: f32,
+}
+
+impl FeatureLayerConfig {
+    pub const DEFAULT_CAMERA_WIDTH: f32 = 24.0;
+
+    /// Square screen and minimap layers of the given sizes.
+    pub fn new(screen: u32, minimap: u32) -> Self {
+        Self {
+            screen: (screen, screen),
+            minimap: (minimap, minimap),
+            camera_width: Self::DEFAULT_CAMERA_WIDTH,
+        }
+    }
+}
+
+impl Default for InterfaceConfig {
+    fn default() -> Self {
+        Self {
+            show_cloaked: true,
+            show_burrowed_shadows: true,
+            show_placeholders: true,
+            raw_affects_selection: false,
+            crop_to_playable_area: false,
+            disable_fog: false,
+            feature_layers: None,
+        }
+    }
+}
+
+impl From<InterfaceConfig> for InterfaceOptions {
+    fn from(value: InterfaceConfig) -> Self {
+        let size = |(x, y): (u32, u32)| Size2DI {
+            x: Some(x as i32),
+            y: Some(y as i32),
+            ..Default::default()
+        };
+
+        let feature_layer = value.feature_layers.map(|layers| SpatialCameraSetup {
+            resolution: MessageField::some(size(layers.screen)),
+            minimap_resolution: MessageField::some(size(layers.minimap)),
+            width: Some(layers.camera_width),
+            crop_to_playable_area: Some(value.crop_to_playable_area),
+            ..Default::default()
+        });
+
+        Self {
+            raw: Some(true),
+            score: Some(true),
+            show_cloaked: Some(value.show_cloaked),
+            show_burrowed_shadows: Some(value.show_burrowed_shadows),
+            show_placeholders: Some(value.show_placeholders),
+            raw_affects_selection: Some(value.raw_affects_selection),
+            raw_crop_to_playable_area: Some(value.crop_to_playable_area),
+            feature_layer: MessageField::from_option(feature_layer),
+            ..Default::default()
+        }
+    }
+}
+
+/// Crop the map's grids to the playable area and make every coordinate relative to it.
+///
+/// Grids which the game has already cropped are left as they are.
+pub(super) fn crop_to_playable_area(start: &mut StartRaw) {
+    let area = start.playable_area.clone();
+    let (x0, y0) = (area.p0.x(), area.p0.y());
+    let (width, height) = (area.p1.x() - x0, area.p1.y() - y0);
+
+    let map_size = start.map_size.clone();
+    let grids = [
+        &mut start.pathing_grid,
+        &mut start.terrain_height,
+        &mut start.placement_grid,
+    ];
+    for image in grids.into_iter().filter_map(|grid| grid.as_mut()) {
+        if image.size.x() == map_size.x() && image.size.y() == map_size.y() {
+            let cropped = crop_image(image, x0, y0, width, height);
+            *image = cropped;
+        }
+    }
+
+    let size = Size2DI {
+        x: Some(width),
+        y: Some(height),
+        ..Default::default()
+    };
+    start.map_size = MessageField::some(size);
+
+    let point = |x: i32, y: i32| PointI {
+        x: Some(x),
+        y: Some(y),
+        ..Default::default()
+    };
+    start.playable_area = MessageField::some(RectangleI {
+        p0: MessageField::some(point(0, 0)),
+        p1: MessageField::some(point(width, height)),
+        ..Default::default()
+    });
+
+    for location in &mut start.start_locations {
+        *location = Point2D {
+            x: Some(location.x() - x0 as f32),
+            y: Some(location.y() - y0 as f32),
+            ..Default::default()
+        };
+    }
+}
+
+/// Crop an image with either 1 or 8 bits per pixel. Rows of 1 bit images aren't padded, each
+/// pixel following on from the last.
+fn crop_image(image: &ImageData, x0: i32, y0: i32, width: i32, height: i32) -> ImageData {
+    let bits_per_pixel = image.bits_per_pixel();
+    let stride = image.size.x() as usize;
+    let data = image.data();
+
+    let pixel = |x: usize, y: usize| {
+        let i = y * stride + x;
+        match bits_per_pixel {
+            1 => (data[i / 8] >> (7 - i % 8)) & 1,
+            _ => data[i],
+        }
+    };
+
+    let pixels = (y0..y0 + height)
+        .flat_map(|y| (x0..x0 + width).map(move |x| (x as usize, y as usize)))
+        .map(|(x, y)| pixel(x, y));
+
+    let cropped: Vec<u8> = match bits_per_pixel {
+        1 => {
+            let pixels = pixels.collect::<Vec<_>>();
+            pixels
+                .chunks(8)
+                .map(|bits| {
+                    bits.iter()
+                        .enumerate()
+                        .fold(0, |byte, (i, bit)| byte | (bit << (7 - i)))
+                })
+                .collect()
+        }
+        _ => pixels.collect(),
+    };
+
+    ImageData {
+        bits_per_pixel: Some(bits_per_pixel),
+        size: MessageField::some(Size2DI {
+            x: Some(width),
+            y: Some(height),
+            ..Default::default()
+        }),
+        data: Some(cropped.into()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(bits_per_pixel: i32, width: i32, height: i32, data: Vec<u8>) -> ImageData {
+        ImageData {
+            bits_per_pixel: Some(bits_per_pixel),
+            size: MessageField::some(Size2DI {
+                x: Some(width),
+                y: Some(height),
+                ..Default::default()
+            }),
+            data: Some(data.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn crops_images() {
+        let bytes = image(8, 4, 4, (0..16).collect());
+        assert_eq!(crop_image(&bytes, 1, 1, 2, 2).data(), [5, 6, 9, 10]);
+
+        let bits = image(1, 8, 2, vec![0b1010_1010, 0b0000_1111]);
+        let cropped = crop_image(&bits, 2, 0, 4, 2);
+        assert_eq!(cropped.data(), [0b1010_0011]);
+        assert_eq!((cropped.size.x(), cropped.size.y()), (4, 2));
+    }
+
+    #[test]
+    fn crops_map_to_playable_area() {
+        let point = |x: i32, y: i32| PointI {
+            x: Some(x),
+            y: Some(y),
+            ..Default::default()
+        };
+        let mut start = StartRaw {
+            map_size: MessageField::some(Size2DI {
+                x: Some(4),
+                y: Some(4),
+                ..Default::default()
+            }),
+            terrain_height: MessageField::some(image(8, 4, 4, (0..16).collect())),
+            playable_area: MessageField::some(RectangleI {
+                p0: MessageField::some(point(1, 1)),
+                p1: MessageField::some(point(3, 3)),
+                ..Default::default()
+            }),
+            start_locations: vec![Point2D {
+                x: Some(2.5),
+                y: Some(1.5),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        crop_to_playable_area(&mut start);
+        assert_eq!((start.map_size.x(), start.map_size.y()), (2, 2));
+        assert_eq!(start.terrain_height.data(), [5, 6, 9, 10]);
+        assert_eq!(start.playable_area.p1.x(), 2);
+        let location = &start.start_locations[0];
+        assert_eq!((location.x(), location.y()), (1.5, 0.5));
+    }
+}
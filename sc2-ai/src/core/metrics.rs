@@ -0,0 +1,339 @@
+//! Timing of requests, schedules and whole steps, for seeing where the time of each step goes.
+//!
+//! Timings are kept as histograms and exported whenever a game ends, or the app exits part way
+//! through a game. Each export appends a JSON summary to a file and optionally overwrites a file
+//! with the same metrics in the Prometheus text format.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::Write as _,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    app::{App, AppExit, MainScheduleOrder},
+    ecs::{
+        event::EventReader,
+        schedule::ScheduleLabel,
+        system::{Res, ResMut, Resource},
+    },
+};
+use serde::Serialize;
+use tracing::{error, info};
+
+use super::{CoreError, GameResult, client::Client, trace::RequestKind};
+
+/// Upper bounds of each histogram bucket, in milliseconds.
+const BUCKETS_MS: [f64; 11] = [
+    0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0,
+];
+
+/// Distribution of durations.
+#[derive(Serialize, Default, Clone, Debug, PartialEq)]
+pub struct Histogram {
+    count: u64,
+    sum_ms: f64,
+    min_ms: f64,
+    max_ms: f64,
+    /// Number of durations no longer than each bound of [`BUCKETS_MS`], with the last counting
+    /// every longer duration.
+    buckets: [u64; BUCKETS_MS.len() + 1],
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let ms = duration.as_secs_f64() * 1000.0;
+
+        if self.count == 0 || ms < self.min_ms {
+            self.min_ms = ms;
+        }
+        self.max_ms = self.max_ms.max(ms);
+        self.count += 1;
+        self.sum_ms += ms;
+
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| ms <= bound)
+            .unwrap_or(BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.sum_ms / self.count as f64 / 1000.0)
+    }
+
+    /// Write the histogram as a Prometheus metric, in seconds.
+    fn write_prometheus(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS_MS.iter().zip(&self.buckets) {
+            cumulative += count;
+            let le = bound / 1000.0;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{separator}le=\"{le}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {}",
+            self.count
+        );
+
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum_ms / 1000.0);
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count);
+    }
+}
+
+/// Timings of the current game.
+#[derive(Resource, Serialize, Default, Clone, Debug)]
+pub struct Metrics {
+    requests: BTreeMap<RequestKind, Histogram>,
+    schedules: BTreeMap<&'static str, Histogram>,
+    steps: Histogram,
+    /// Number of steps which took longer than the step period.
+    overruns: u64,
+
+    #[serde(skip)]
+    started: HashMap<&'static str, Instant>,
+}
+
+impl Metrics {
+    /// Record the wall time of a whole step, counting it as an overrun if it took longer than
+    /// `period`.
+    pub fn record_step(&mut self, duration: Duration, period: Option<Duration>) {
+        self.steps.record(duration);
+        if period.is_some_and(|period| duration > period) {
+            self.overruns += 1;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.steps.count == 0 && self.requests.is_empty() && self.schedules.is_empty()
+    }
+
+    fn start(&mut self, schedule: &'static str) {
+        self.started.insert(schedule, Instant::now());
+    }
+
+    fn stop(&mut self, schedule: &'static str) {
+        if let Some(start) = self.started.remove(schedule) {
+            self.schedules
+                .entry(schedule)
+                .or_default()
+                .record(start.elapsed());
+        }
+    }
+
+    fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# TYPE sc2_ai_request_seconds histogram\n");
+        for (kind, histogram) in &self.requests {
+            let labels = format!("request=\"{kind}\"");
+            histogram.write_prometheus(&mut out, "sc2_ai_request_seconds", &labels);
+        }
+
+        out.push_str("# TYPE sc2_ai_schedule_seconds histogram\n");
+        for (schedule, histogram) in &self.schedules {
+            let labels = format!("schedule=\"{schedule}\"");
+            histogram.write_prometheus(&mut out, "sc2_ai_schedule_seconds", &labels);
+        }
+
+        out.push_str("# TYPE sc2_ai_step_seconds histogram\n");
+        self.steps
+            .write_prometheus(&mut out, "sc2_ai_step_seconds", "");
+
+        out.push_str("# TYPE sc2_ai_step_overruns_total counter\n");
+        let _ = writeln!(out, "sc2_ai_step_overruns_total {}", self.overruns);
+        out
+    }
+}
+
+/// Where to export [`Metrics`] to.
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub(super) struct MetricsExport {
+    pub json: PathBuf,
+    pub prometheus: Option<PathBuf>,
+}
+
+/// Bevy systems.
+impl Metrics {
+    /// Record the time taken by each request sent since the last update.
+    pub(super) fn collect_requests(client: Option<ResMut<Client>>, mut metrics: ResMut<Metrics>) {
+        let Some(mut client) = client else {
+            return;
+        };
+
+        for (kind, duration) in client.take_timings() {
+            metrics.requests.entry(kind).or_default().record(duration);
+        }
+    }
+
+    /// Export the metrics of a game once it's ended or the app is exiting, then start afresh for
+    /// the next game.
+    ///
+    /// Runs before the next game is started, so any error is taken as the app exiting, as it will
+    /// once the error is handled later in the update.
+    pub(super) fn export(
+        mut results: EventReader<GameResult>,
+        mut exits: EventReader<AppExit>,
+        mut errors: EventReader<CoreError>,
+        export: Res<MetricsExport>,
+        mut metrics: ResMut<Metrics>,
+    ) {
+        // Events stay readable on the following update too, so must be read rather than peeked at
+        // for each game to be exported once.
+        let ended = results.read().count() > 0;
+        let exiting = exits.read().count() > 0 || errors.read().count() > 0;
+        if !(ended || exiting) || metrics.is_empty() {
+            return;
+        }
+
+        info!(
+            "Steps took {:.1?} on average, {} of {} overran",
+            metrics.steps.mean(),
+            metrics.overruns,
+            metrics.steps.count
+        );
+
+        let json = serde_json::to_string(&*metrics).map_err(std::io::Error::from);
+        let appended = json.and_then(|json| {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&export.json)?;
+            writeln!(file, "{json}")
+        });
+        if let Err(e) = appended {
+            error!("Failed to write metrics to {}: {e}", export.json.display());
+        }
+
+        if let Some(path) = &export.prometheus {
+            if let Err(e) = std::fs::write(path, metrics.to_prometheus()) {
+                error!("Failed to write metrics to {}: {e}", path.display());
+            }
+        }
+
+        *metrics = Metrics::default();
+    }
+}
+
+#[derive(ScheduleLabel, Clone, Debug, Hash, PartialEq, Eq)]
+struct TimerStart(&'static str);
+
+#[derive(ScheduleLabel, Clone, Debug, Hash, PartialEq, Eq)]
+struct TimerStop(&'static str);
+
+pub trait TimeScheduleExt {
+    /// Record the time taken by a schedule of the main schedule each time it runs, as `name`.
+    fn time_schedule(
+        &mut self,
+        schedule: impl ScheduleLabel + Clone,
+        name: &'static str,
+    ) -> &mut Self;
+
+    /// Record the time taken by a startup schedule of the main schedule, as `name`.
+    fn time_startup_schedule(
+        &mut self,
+        schedule: impl ScheduleLabel + Clone,
+        name: &'static str,
+    ) -> &mut Self;
+}
+
+impl TimeScheduleExt for App {
+    fn time_schedule(
+        &mut self,
+        schedule: impl ScheduleLabel + Clone,
+        name: &'static str,
+    ) -> &mut Self {
+        add_timers(self, name);
+
+        let mut order = self.world_mut().resource_mut::<MainScheduleOrder>();
+        order.insert_before(schedule.clone(), TimerStart(name));
+        order.insert_after(schedule, TimerStop(name));
+        self
+    }
+
+    fn time_startup_schedule(
+        &mut self,
+        schedule: impl ScheduleLabel + Clone,
+        name: &'static str,
+    ) -> &mut Self {
+        add_timers(self, name);
+
+        let mut order = self.world_mut().resource_mut::<MainScheduleOrder>();
+        order.insert_startup_before(schedule.clone(), TimerStart(name));
+        order.insert_startup_after(schedule, TimerStop(name));
+        self
+    }
+}
+
+fn add_timers(app: &mut App, name: &'static str) {
+    app.init_resource::<Metrics>();
+    app.add_systems(TimerStart(name), move |mut metrics: ResMut<Metrics>| {
+        metrics.start(name);
+    });
+    app.add_systems(TimerStop(name), move |mut metrics: ResMut<Metrics>| {
+        metrics.stop(name);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_durations_into_buckets() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.mean(), Duration::ZERO);
+
+        for ms in [3, 1, 2000] {
+            histogram.record(Duration::from_millis(ms));
+        }
+
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.min_ms, 1.0);
+        assert_eq!(histogram.max_ms, 2000.0);
+        assert_eq!(histogram.buckets, [0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert!((histogram.mean().as_secs_f64() - 0.668).abs() < 1e-9);
+    }
+
+    #[test]
+    fn exports_prometheus_histograms() {
+        let mut metrics = Metrics::default();
+        let step = metrics.requests.entry(RequestKind::Step).or_default();
+        step.record(Duration::from_millis(1));
+        step.record(Duration::from_millis(3));
+        metrics.record_step(Duration::from_millis(30), Some(Duration::from_millis(20)));
+
+        let out = metrics.to_prometheus();
+        let lines = out.lines().collect::<Vec<_>>();
+
+        for expected in [
+            r#"sc2_ai_request_seconds_bucket{request="step",le="0.0005"} 0"#,
+            r#"sc2_ai_request_seconds_bucket{request="step",le="0.001"} 1"#,
+            r#"sc2_ai_request_seconds_bucket{request="step",le="0.005"} 2"#,
+            r#"sc2_ai_request_seconds_bucket{request="step",le="+Inf"} 2"#,
+            r#"sc2_ai_request_seconds_count{request="step"} 2"#,
+            r#"sc2_ai_step_seconds_bucket{le="0.05"} 1"#,
+            "sc2_ai_step_seconds_count 1",
+            "sc2_ai_step_overruns_total 1",
+        ] {
+            assert!(lines.contains(&expected), "{expected} missing from:\n{out}");
+        }
+        assert!(!out.contains("sc2_ai_schedule_seconds_"));
+    }
+}
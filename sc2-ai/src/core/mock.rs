@@ -0,0 +1,715 @@
+//! In-process stand-in for the game's `/sc2api` websocket endpoint.
+//!
+//! Allows the core request loop and everything downstream of it to run without a StarCraft II
+//! process. Requests are answered by a [`MockHandler`], which is either the canned [`MockGame`] or
+//! any closure mapping a [`Request`] to a [`Response`]. A closure can wrap a [`MockGame`] in order
+//! to script specific responses while leaving the rest canned.
+
+use std::net::{TcpListener, TcpStream};
+
+use anyhow::anyhow;
+use num_traits::ToPrimitive as _;
+use protobuf::{Message as _, MessageField};
+use tracing::{error, info};
+use tungstenite::{Message, WebSocket};
+
+use sc2_proto::{
+    common::{ImageData, Point, Point2D, PointI, RectangleI, Size2DI},
+    debug::DebugCommand,
+    query::{
+        ResponseQueryAvailableAbilities, ResponseQueryBuildingPlacement, ResponseQueryPathing,
+    },
+    raw::{Alliance, DisplayType, ObservationRaw, StartRaw, Unit},
+    sc2api::{
+        self, PlayerCommon, PlayerResult, Request, Response, ResponseGameInfo, Status,
+        request::Request as ApiRequest,
+    },
+    unit::TypeId,
+};
+
+const HOST: &str = "127.0.0.1";
+
+/// Produces a response for each request received by a [`MockServer`].
+pub trait MockHandler: Send + 'static {
+    fn respond(&mut self, request: Request) -> Response;
+}
+
+impl<F> MockHandler for F
+where
+    F: FnMut(Request) -> Response + Send + 'static,
+{
+    fn respond(&mut self, request: Request) -> Response {
+        self(request)
+    }
+}
+
+/// Websocket server answering game API requests on a background thread.
+///
+/// Accepts a single client connection. The thread exits once the client disconnects or sends
+/// [`Request::quit`].
+#[derive(Debug)]
+pub struct MockServer {
+    port: u16,
+}
+
+impl MockServer {
+    pub fn spawn(handler: impl MockHandler) -> std::io::Result<Self> {
+        let listener = TcpListener::bind((HOST, 0))?;
+        let port = listener.local_addr()?.port();
+
+        std::thread::spawn(move || {
+            if let Err(e) = serve(listener, handler) {
+                error!("Mock server stopped: {e}");
+            }
+        });
+
+        Ok(Self { port })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+fn serve(listener: TcpListener, mut handler: impl MockHandler) -> Result<(), anyhow::Error> {
+    let (stream, _) = listener.accept()?;
+    let mut socket: WebSocket<TcpStream> =
+        tungstenite::accept(stream).map_err(|e| anyhow!("Websocket handshake failed: {e}"))?;
+
+    loop {
+        let data = match socket.read() {
+            Ok(Message::Binary(data)) => data,
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => continue,
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let request = Request::parse_from_bytes(&data)?;
+        let quit = request.has_quit();
+
+        let response = handler.respond(request);
+        socket.send(Message::binary(response.write_to_bytes()?))?;
+
+        if quit {
+            return Ok(());
+        }
+    }
+}
+
+/// Canned single player game.
+///
+/// Serves a small flat map containing one Zerg base and a handful of resource clusters. Each step
+/// advances the game loop and the game ends in a victory once its game length has elapsed, see
+/// [`MockGame::with_game_length`]. Units may be created and killed through debug commands.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockGame {
+    start_raw: StartRaw,
+    units: Vec<Unit>,
+    /// Tag of the last unit created.
+    next_tag: u64,
+    player: PlayerCommon,
+    game_loop: u32,
+    game_length: Option<u32>,
+    status: Status,
+}
+
+impl Default for MockGame {
+    fn default() -> Self {
+        let (width, height) = (64, 64);
+        let mut units = Vec::new();
+
+        let mut tag = 1;
+        let mut unit = |unit_type: TypeId, alliance: Alliance, pos: (f32, f32)| {
+            tag += 1;
+            let owner = if alliance == Alliance::Self_ { 1 } else { 16 };
+            Self::unit(tag, unit_type.to_u32().unwrap(), owner, pos)
+        };
+
+        units.push(unit(TypeId::Hatchery, Alliance::Self_, (16.5, 16.5)));
+        units.push(unit(TypeId::Overlord, Alliance::Self_, (16.5, 20.0)));
+        units.extend((0..3).map(|i| unit(TypeId::Larva, Alliance::Self_, (15.0 + i as f32, 14.0))));
+        units.extend((0..12).map(|i| {
+            unit(
+                TypeId::Drone,
+                Alliance::Self_,
+                (18.0, 13.0 + i as f32 * 0.5),
+            )
+        }));
+
+        // One resource cluster by our base and one at a natural expansion.
+        for (x, y) in [(24.0, 12.5), (40.0, 44.5)] {
+            units.extend((0..8).map(|i| {
+                let patch = if i % 2 == 0 {
+                    TypeId::MineralField
+                } else {
+                    TypeId::MineralField750
+                };
+                unit(patch, Alliance::Neutral, (x + (i % 2) as f32, y + i as f32))
+            }));
+            units.push(unit(
+                TypeId::VespeneGeyser,
+                Alliance::Neutral,
+                (x - 4.5, y - 4.5),
+            ));
+            units.push(unit(
+                TypeId::VespeneGeyser,
+                Alliance::Neutral,
+                (x - 4.5, y + 11.5),
+            ));
+        }
+
+        let player = PlayerCommon {
+            player_id: Some(1),
+            minerals: Some(50),
+            vespene: Some(0),
+            food_cap: Some(14),
+            food_used: Some(12),
+            ..Default::default()
+        };
+
+        Self {
+            start_raw: Self::flat_map(width, height),
+            next_tag: units.len() as u64 + 1,
+            units,
+            player,
+            game_loop: 0,
+            game_length: Some(Self::DEFAULT_GAME_LENGTH),
+            status: Status::launched,
+        }
+    }
+}
+
+impl MockGame {
+    /// One minute of game time.
+    pub const DEFAULT_GAME_LENGTH: u32 = 22 * 60;
+
+    /// End the game once `loops` game loops have elapsed, or never if [`None`].
+    pub fn with_game_length(mut self, loops: Option<u32>) -> Self {
+        self.game_length = loops;
+        self
+    }
+
+    fn unit(tag: u64, unit_type: u32, owner: i32, pos: (f32, f32)) -> Unit {
+        let alliance = match owner {
+            1 => Alliance::Self_,
+            16 => Alliance::Neutral,
+            _ => Alliance::Enemy,
+        };
+
+        Unit {
+            display_type: Some(DisplayType::Visible.into()),
+            alliance: Some(alliance.into()),
+            tag: Some(tag),
+            unit_type: Some(unit_type),
+            owner: Some(owner),
+            pos: MessageField::some(Point {
+                x: Some(pos.0),
+                y: Some(pos.1),
+                z: Some(0.0),
+                ..Default::default()
+            }),
+            build_progress: Some(1.0),
+            health: Some(40.0),
+            health_max: Some(40.0),
+            ..Default::default()
+        }
+    }
+
+    /// Apply the unit spawning and killing debug commands. Others are ignored.
+    fn debug(&mut self, command: &DebugCommand) {
+        if command.has_create_unit() {
+            let create = command.create_unit();
+            let pos = (create.pos.x(), create.pos.y());
+
+            for _ in 0..create.quantity() {
+                self.next_tag += 1;
+                let unit = Self::unit(self.next_tag, create.unit_type(), create.owner(), pos);
+                self.units.push(unit);
+            }
+        } else if command.has_kill_unit() {
+            let tags = &command.kill_unit().tag;
+            self.units.retain(|unit| !tags.contains(&unit.tag()));
+        }
+    }
+
+    /// Map with every cell pathable and placeable at a constant height.
+    fn flat_map(width: i32, height: i32) -> StartRaw {
+        let size = Size2DI {
+            x: Some(width),
+            y: Some(height),
+            ..Default::default()
+        };
+
+        let image = |bits_per_pixel: i32, byte: u8| {
+            let len = (width * height * bits_per_pixel / 8) as usize;
+            ImageData {
+                bits_per_pixel: Some(bits_per_pixel),
+                size: MessageField::some(size.clone()),
+                data: Some(vec![byte; len].into()),
+                ..Default::default()
+            }
+        };
+
+        let corner = |x: i32, y: i32| PointI {
+            x: Some(x),
+            y: Some(y),
+            ..Default::default()
+        };
+
+        StartRaw {
+            map_size: MessageField::some(size.clone()),
+            pathing_grid: MessageField::some(image(1, 0xFF)),
+            terrain_height: MessageField::some(image(8, 128)),
+            placement_grid: MessageField::some(image(1, 0xFF)),
+            playable_area: MessageField::some(RectangleI {
+                p0: MessageField::some(corner(0, 0)),
+                p1: MessageField::some(corner(width, height)),
+                ..Default::default()
+            }),
+            start_locations: vec![Point2D {
+                x: Some(width as f32 - 16.5),
+                y: Some(height as f32 - 16.5),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn observation(&self) -> sc2api::ResponseObservation {
+        let mut response = sc2api::ResponseObservation::new();
+
+        let observation = response.observation.mut_or_insert_default();
+        observation.set_game_loop(self.game_loop);
+        observation.player_common = MessageField::some(self.player.clone());
+        observation.raw_data = MessageField::some(ObservationRaw {
+            units: self.units.clone(),
+            ..Default::default()
+        });
+
+        if self.status == Status::ended {
+            response.player_result = [(1, sc2api::Result::Victory), (2, sc2api::Result::Defeat)]
+                .into_iter()
+                .map(|(player_id, result)| PlayerResult {
+                    player_id: Some(player_id),
+                    result: Some(result.into()),
+                    ..Default::default()
+                })
+                .collect();
+        }
+
+        response
+    }
+}
+
+impl MockHandler for MockGame {
+    fn respond(&mut self, request: Request) -> Response {
+        let mut response = Response::new();
+
+        match request.request {
+            Some(ApiRequest::CreateGame(_)) => {
+                self.game_loop = 0;
+                self.status = Status::init_game;
+                response.mut_create_game();
+            }
+            Some(ApiRequest::JoinGame(_)) => {
+                self.status = Status::in_game;
+                response.mut_join_game().set_player_id(1);
+            }
+            Some(ApiRequest::RestartGame(_)) => {
+                self.game_loop = 0;
+                self.status = Status::in_game;
+                response.mut_restart_game();
+            }
+            Some(ApiRequest::GameInfo(_)) => {
+                response.set_game_info(ResponseGameInfo {
+                    map_name: Some("Mock".to_owned()),
+                    start_raw: MessageField::some(self.start_raw.clone()),
+                    ..Default::default()
+                });
+            }
+            Some(ApiRequest::Data(_)) => {
+                response.mut_data();
+            }
+            Some(ApiRequest::Observation(_)) => response.set_observation(self.observation()),
+            Some(ApiRequest::Action(request)) => {
+                response.mut_action().result = request
+                    .actions
+                    .iter()
+                    .map(|_| sc2_proto::error::ActionResult::Success.into())
+                    .collect();
+            }
+            Some(ApiRequest::Query(request)) => {
+                let query = response.mut_query();
+                query.pathing = request
+                    .pathing
+                    .iter()
+                    .map(|_| ResponseQueryPathing::default())
+                    .collect();
+                query.placements = request
+                    .placements
+                    .iter()
+                    .map(|_| ResponseQueryBuildingPlacement {
+                        result: Some(sc2_proto::error::ActionResult::Success.into()),
+                        ..Default::default()
+                    })
+                    .collect();
+                query.abilities = request
+                    .abilities
+                    .iter()
+                    .map(|_| ResponseQueryAvailableAbilities::default())
+                    .collect();
+            }
+            Some(ApiRequest::Debug(request)) => {
+                request.debug.iter().for_each(|command| self.debug(command));
+                response.mut_debug();
+            }
+            Some(ApiRequest::Step(request)) => {
+                self.game_loop += request.count().max(1);
+                if self
+                    .game_length
+                    .is_some_and(|length| self.game_loop >= length)
+                {
+                    info!("Mock game reached loop {}, ending", self.game_loop);
+                    self.status = Status::ended;
+                }
+                response.mut_step().set_simulation_loop(self.game_loop);
+            }
+            Some(ApiRequest::SaveReplay(_)) => {
+                response.mut_save_replay();
+            }
+            Some(ApiRequest::LeaveGame(_)) => {
+                self.status = Status::launched;
+                response.mut_leave_game();
+            }
+            Some(ApiRequest::Quit(_)) => {
+                self.status = Status::quit;
+                response.mut_quit();
+            }
+            Some(ApiRequest::Ping(_)) => {
+                response.mut_ping();
+            }
+            _ => response
+                .error
+                .push("Request not supported by mock server".to_owned()),
+        }
+
+        response.set_status(self.status);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use bevy::{
+        app::{App, AppExit, Last, Startup, Update},
+        ecs::{
+            entity::Entity,
+            event::{Event, EventReader},
+            query::With,
+            system::{Commands, Local, Query},
+        },
+    };
+
+    use super::*;
+    use crate::{
+        core::{
+            ApiObservation, CorePlugin, GameResult, GameSetup, InterfaceConfig, Outcome, PlayerId,
+            QueryId, QueryResponse, QueryResult, Session, StartupMode, client::Client,
+            restart::Role,
+        },
+        game::{
+            GamePlugin, action::ActionCommandsExt as _, debug::DebugCommandsExt as _,
+            entity::unit::Worker, geometry::Vec2, query::QueryCommandsExt as _,
+        },
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const GAME_LENGTH: u32 = 8;
+    const MAX_UPDATES: usize = 100;
+
+    type Shared<T> = Arc<Mutex<Vec<T>>>;
+
+    /// Plugin which has joined a game of `game`, along with every request the mock received.
+    fn join(game: MockGame, games: u32, record: Option<&Path>) -> (CorePlugin, Shared<Request>) {
+        let received = Shared::<Request>::default();
+        let handler = {
+            let received = received.clone();
+            let mut game = game;
+            move |request: Request| {
+                received.lock().unwrap().push(request.clone());
+                game.respond(request)
+            }
+        };
+
+        let server = MockServer::spawn(handler).unwrap();
+        let mut client = Client::connect(HOST, server.port().into(), TIMEOUT).unwrap();
+        if let Some(path) = record {
+            client.record_to(path).unwrap();
+        }
+
+        let setup = GameSetup::default();
+        client
+            .start_game(
+                "Mock.SC2Map".to_owned(),
+                setup.player(),
+                setup.opponent(),
+                false,
+                None,
+            )
+            .unwrap();
+        let player = client
+            .join_game(setup.player(), None, InterfaceConfig::default())
+            .unwrap();
+
+        let plugin = CorePlugin::new(
+            StartupMode::Mock { game_length: None },
+            "Mock".to_owned(),
+            false,
+        )
+        .with_games(games);
+        *plugin.session.lock().unwrap() = Some(Session {
+            process: None,
+            client,
+            player: PlayerId(player),
+            role: Role::Single,
+            observer: false,
+        });
+
+        (plugin, received)
+    }
+
+    fn app(core: CorePlugin) -> App {
+        let mut app = App::new();
+        app.add_plugins(core).add_plugins(GamePlugin);
+        app
+    }
+
+    fn collect<E>(events: Shared<E>) -> impl FnMut(EventReader<E>)
+    where
+        E: Event + Clone,
+    {
+        move |mut reader: EventReader<E>| events.lock().unwrap().extend(reader.read().cloned())
+    }
+
+    /// Update the app until it exits, returning the result of each game played.
+    fn run(app: &mut App) -> Vec<GameResult> {
+        let results = Shared::<GameResult>::default();
+        app.add_systems(Last, collect(results.clone()));
+
+        for _ in 0..MAX_UPDATES {
+            app.update();
+            if let Some(exit) = app.should_exit() {
+                assert_eq!(exit, AppExit::Success);
+                return results.lock().unwrap().clone();
+            }
+        }
+        panic!("App should exit once the game ends");
+    }
+
+    fn requests(received: &Shared<Request>, kind: fn(&Request) -> bool) -> Vec<Request> {
+        received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request| kind(request))
+            .cloned()
+            .collect()
+    }
+
+    fn count_units(app: &App, unit_type: TypeId, owner: i32) -> usize {
+        app.world()
+            .resource::<ApiObservation>()
+            .units
+            .iter()
+            .filter(|unit| unit.unit_type() == unit_type.to_u32().unwrap())
+            .filter(|unit| unit.owner() == owner)
+            .count()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sc2-ai-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn plays_game_to_victory() {
+        let game = MockGame::default().with_game_length(Some(GAME_LENGTH));
+        let (core, received) = join(game, 1, None);
+
+        let mut app = app(core);
+        app.add_systems(
+            Update,
+            |mut commands: Commands, workers: Query<Entity, With<Worker>>| {
+                let workers = workers.iter().collect::<Box<_>>();
+                commands.move_units(&workers, Vec2::new(32.0, 32.0));
+            },
+        );
+
+        let results = run(&mut app);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].outcome, Outcome::Victory);
+        assert!(results[0].game_loop >= GAME_LENGTH);
+
+        let workers = app
+            .world_mut()
+            .query_filtered::<Entity, With<Worker>>()
+            .iter(app.world())
+            .count();
+        assert_eq!(workers, 12);
+
+        let moves = requests(&received, Request::has_action)
+            .into_iter()
+            .flat_map(|request| request.action().actions.clone())
+            .filter(|action| action.action_raw.has_unit_command())
+            .collect::<Vec<_>>();
+        assert!(!moves.is_empty());
+        assert!(
+            moves
+                .iter()
+                .all(|action| action.action_raw.unit_command().unit_tags.len() == 12)
+        );
+
+        assert!(!requests(&received, Request::has_step).is_empty());
+        assert_eq!(requests(&received, Request::has_leave_game).len(), 1);
+    }
+
+    #[test]
+    fn restarts_pipelined_game_once_result_observed() {
+        let game = MockGame::default().with_game_length(Some(GAME_LENGTH));
+        let (core, received) = join(game, 2, None);
+
+        let mut app = app(core.with_pipelining(true));
+        let results = run(&mut app);
+        assert_eq!(results.len(), 2);
+        assert!(
+            results
+                .iter()
+                .all(|result| result.outcome == Outcome::Victory && result.game_loop >= GAME_LENGTH)
+        );
+        assert_eq!(requests(&received, Request::has_restart_game).len(), 1);
+        assert!(!requests(&received, Request::has_step).is_empty());
+    }
+
+    #[test]
+    fn plays_back_recording() {
+        let path = temp_path("recording");
+
+        let game = MockGame::default().with_game_length(Some(GAME_LENGTH));
+        let (core, _) = join(game, 1, Some(&path));
+        let recorded = run(&mut app(core));
+
+        let playback = StartupMode::Playback { path: path.clone() };
+        let core = CorePlugin::new(playback, "Mock".to_owned(), false);
+        let played_back = run(&mut app(core));
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(played_back, recorded);
+    }
+
+    #[test]
+    fn exits_on_failed_startup() {
+        let missing = StartupMode::Playback {
+            path: temp_path("missing"),
+        };
+        let mut app = app(CorePlugin::new(missing, "Mock".to_owned(), false));
+
+        // Game systems are skipped rather than running against a game that was never joined.
+        app.update();
+        assert_eq!(app.should_exit(), Some(AppExit::error()));
+    }
+
+    #[test]
+    fn restarts_game_in_place() {
+        let game = MockGame::default().with_game_length(Some(GAME_LENGTH));
+        let (core, received) = join(game, 2, None);
+
+        let mut app = app(core);
+        let results = run(&mut app);
+        assert_eq!(results.len(), 2);
+        assert!(
+            results
+                .iter()
+                .all(|result| result.outcome == Outcome::Victory)
+        );
+        assert_eq!(requests(&received, Request::has_restart_game).len(), 1);
+
+        // Entities of the first game are despawned before those of the second are created.
+        let workers = app
+            .world_mut()
+            .query_filtered::<Entity, With<Worker>>()
+            .iter(app.world())
+            .count();
+        assert_eq!(workers, 12);
+    }
+
+    #[test]
+    fn answers_queries() {
+        let game = MockGame::default().with_game_length(Some(GAME_LENGTH));
+        let (core, received) = join(game, 1, None);
+
+        let ids = Shared::<QueryId>::default();
+        let results = Shared::<QueryResult>::default();
+
+        let mut app = app(core);
+        app.add_systems(Startup, {
+            let ids = ids.clone();
+            move |mut commands: Commands| {
+                let id = commands.query_path(Vec2::new(16.0, 16.0), Vec2::new(40.0, 40.0));
+                ids.lock().unwrap().push(id);
+            }
+        });
+        app.add_systems(Update, collect(results.clone()));
+        run(&mut app);
+
+        let ids = ids.lock().unwrap();
+        let results = results.lock().unwrap();
+        assert_eq!(requests(&received, Request::has_query).len(), 1);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, ids[0]);
+        // The mock reports a distance of zero, meaning there's no path.
+        assert_eq!(results[0].response, QueryResponse::Pathing(None));
+    }
+
+    #[test]
+    fn applies_debug_commands() {
+        let game = MockGame::default().with_game_length(Some(GAME_LENGTH));
+        let (core, received) = join(game, 1, None);
+
+        let mut app = app(core);
+        app.add_systems(Startup, |mut commands: Commands| {
+            commands.create_units(TypeId::Zergling, 2, Vec2::new(32.0, 32.0), 3);
+        });
+        app.add_systems(
+            Update,
+            |mut commands: Commands,
+             workers: Query<Entity, With<Worker>>,
+             mut done: Local<bool>| {
+                if !*done {
+                    commands.kill_units(&workers.iter().collect::<Box<_>>());
+                    *done = true;
+                }
+            },
+        );
+        run(&mut app);
+
+        assert_eq!(count_units(&app, TypeId::Zergling, 2), 3);
+        assert_eq!(count_units(&app, TypeId::Drone, 1), 0);
+        assert_eq!(count_units(&app, TypeId::Hatchery, 1), 1);
+
+        let commands = requests(&received, Request::has_debug)
+            .into_iter()
+            .flat_map(|request| request.debug().debug.clone())
+            .collect::<Vec<_>>();
+        assert!(commands.iter().any(DebugCommand::has_create_unit));
+        assert!(commands.iter().any(DebugCommand::has_kill_unit));
+    }
+}
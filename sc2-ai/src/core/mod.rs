@@ -1,130 +1,489 @@
-use std::net::Ipv4Addr;
+use std::{net::Ipv4Addr, path::PathBuf, sync::Mutex, time::Duration};
 
 use bevy::{
     app::{App, AppExit, First, Last, Plugin, PreStartup},
     ecs::{
-        event::EventWriter,
-        system::{Res, ResMut, Resource},
+        event::{Event, EventReader, EventWriter},
+        schedule::{
+            IntoSystemConfigs as _,
+            common_conditions::{not, resource_exists},
+        },
+        system::{Commands, In, IntoSystem as _, Res, ResMut, Resource},
     },
 };
 use protobuf::MessageField;
-use tracing::{error, info};
+use serde::Serialize;
+use tracing::{error, info, warn};
 
-use sc2_proto::{
-    common::Race,
-    sc2api::{
-        self, Difficulty, PlayerSetup, PlayerType, Request, ResponseGameInfo, ResponseObservation,
-        Status,
-    },
-};
+use sc2_proto::sc2api::{self, Request, ResponseGameInfo, ResponseObservation, Status};
 
 mod action;
 mod client;
 mod command;
+mod data;
+mod error;
+mod event;
+mod interface;
+mod metrics;
+mod mock;
 mod process;
-
-use client::Client;
+mod query;
+mod record;
+mod replay;
+mod restart;
+mod setup;
+mod signal;
+mod step;
+mod time;
+mod trace;
+mod versus;
+
+use client::{Client, GamePorts};
+use metrics::MetricsExport;
+use mock::MockServer;
 use process::Process;
+use replay::ReplaySaver;
+use restart::{Restart, Role};
 
 pub use action::Actions;
 pub use command::DebugCommands;
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub use data::GameData;
+pub use error::CoreError;
+pub use event::{AlertEvent, ChatReceived};
+pub use interface::{FeatureLayerConfig, InterfaceConfig};
+pub use metrics::{Metrics, TimeScheduleExt};
+pub use mock::MockGame;
+pub use query::{Queries, QueryId, QueryResponse, QueryResult};
+pub use setup::{AiBuild, Difficulty, GameSetup, OpponentType, Race};
+pub use signal::INTERRUPTED_EXIT_CODE;
+pub use step::{Stepping, WakeCondition};
+pub use time::GameTime;
+pub use trace::{RequestKind, TraceConfig, TraceFormat};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum StartupMode {
     Launch,
-    Connect { addr: Ipv4Addr, port: u16 },
+    Connect {
+        addr: Ipv4Addr,
+        port: u16,
+    },
+    /// Play against an in-process [`MockGame`] instead of a real game. The game ends once
+    /// `game_length` game loops have elapsed, or never if [`None`].
+    Mock {
+        game_length: Option<u32>,
+    },
+    /// Answer requests from a recording of a previous game.
+    Playback {
+        path: PathBuf,
+    },
+    /// Launch the game and observe a replay from the perspective of one of its players. Actions
+    /// and debug commands are discarded rather than sent.
+    Replay {
+        path: PathBuf,
+        player_id: u32,
+    },
+    /// Join a game already created by a ladder server.
+    Ladder {
+        server: Ipv4Addr,
+        game_port: u16,
+        start_port: u16,
+        opponent_id: Option<String>,
+    },
 }
 
 /// Core plugin for managing requests/responses to/from game api and translating the game state to the ECS.
 ///
-/// Game startup is done through this plugin.
+/// Game startup is done through this plugin, unless it was given an already started [`Session`].
 /// Systems are added to read the game state at the start of each tick and send actions back at the end of each tick.
 #[derive(Debug)]
 pub struct CorePlugin {
     mode: StartupMode,
     map: String,
     realtime: bool,
+    pipelined: bool,
+    record: Option<PathBuf>,
+    trace: Option<TraceConfig>,
+    metrics: Option<MetricsExport>,
+    replay_dir: Option<PathBuf>,
+    interface: InterfaceConfig,
+    connect_timeout: Duration,
+    setup: GameSetup,
+    games: u32,
+    step_count: u32,
+    session: Mutex<Option<Session>>,
+}
+
+/// Connection to a game instance which has joined a game.
+#[derive(Debug)]
+struct Session {
+    process: Option<Process>,
+    client: Client,
+    player: PlayerId,
+    role: Role,
+    /// Whether the game was joined as an observer rather than a player.
+    observer: bool,
 }
 
 impl CorePlugin {
+    pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+
     pub fn new(mode: StartupMode, map: String, realtime: bool) -> Self {
         Self {
             mode,
             map,
             realtime,
+            pipelined: false,
+            record: None,
+            trace: None,
+            metrics: None,
+            replay_dir: None,
+            interface: InterfaceConfig::default(),
+            connect_timeout: Self::DEFAULT_CONNECT_TIMEOUT,
+            setup: GameSetup::default(),
+            games: 1,
+            step_count: 1,
+            session: Mutex::new(None),
         }
     }
+
+    /// How long to wait for the game to accept a connection before giving up.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set up the players of the game.
+    ///
+    /// A [`OpponentType::Participant`] or [`OpponentType::Observer`] opponent requires a second
+    /// instance of the bot, see [`CorePlugin::start_versus`].
+    pub fn with_setup(mut self, setup: GameSetup) -> Self {
+        self.setup = setup;
+        self
+    }
+
+    /// Choose what the game includes in observations.
+    pub fn with_interface(mut self, interface: InterfaceConfig) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// Step the game by `count` game loops each update rather than one. Systems may change this
+    /// during the game through [`Stepping`].
+    pub fn with_step_count(mut self, count: u32) -> Self {
+        self.step_count = count;
+        self
+    }
+
+    /// Step the game while the app updates rather than between updates, so stepping the game and
+    /// decoding the next observation overlap with the [`Update`] schedule. Actions then take
+    /// effect a step later than they otherwise would, as do changes to [`Stepping::set_count`],
+    /// since the game has already been stepped by the old count when [`Update`] runs. Not
+    /// supported in realtime.
+    ///
+    /// [`Update`]: bevy::app::Update
+    pub fn with_pipelining(mut self, pipelined: bool) -> Self {
+        self.pipelined = pipelined;
+        self
+    }
+
+    /// Play `games` games in a row, restarting the game in the same game instance rather than
+    /// exiting once a game has ended. Not supported in ladder games.
+    pub fn with_games(mut self, games: u32) -> Self {
+        self.games = games;
+        self
+    }
+
+    /// Record every request/response pair of the game to a file for later playback.
+    pub fn with_recording(mut self, path: PathBuf) -> Self {
+        self.record = Some(path);
+        self
+    }
+
+    /// Write a readable trace of every request and response to a file.
+    pub fn with_trace(mut self, config: TraceConfig) -> Self {
+        self.trace = Some(config);
+        self
+    }
+
+    /// Append a JSON summary of the [`Metrics`] of each game to a file once it ends, optionally
+    /// also writing them in the Prometheus text format to another file.
+    pub fn with_metrics(mut self, json: PathBuf, prometheus: Option<PathBuf>) -> Self {
+        self.metrics = Some(MetricsExport { json, prometheus });
+        self
+    }
+
+    /// Save a replay to `dir` whenever a game ends, including when exiting on an error or Ctrl-C.
+    pub fn with_replay_dir(mut self, dir: PathBuf) -> Self {
+        self.replay_dir = Some(dir);
+        self
+    }
 }
 
-impl Plugin for CorePlugin {
-    fn build(&self, app: &mut App) {
+impl CorePlugin {
+    /// Connect to the game then create and join a game.
+    fn start(&self) -> Result<Session, CoreError> {
         info!("Launching client");
-        let (process, mut client) = match self.mode {
-            StartupMode::Launch => process::launch_client().map(|(p, c)| (Some(p), c)),
+        let (process, mut client) = match &self.mode {
+            StartupMode::Launch | StartupMode::Replay { .. } => {
+                let (process, client) = process::launch_client(self.connect_timeout)?;
+                (Some(process), client)
+            }
             StartupMode::Connect { addr, port } => {
-                Client::connect(&addr.to_string(), port.into()).map(|c| (None, c))
+                let client =
+                    Client::connect(&addr.to_string(), (*port).into(), self.connect_timeout)?;
+                (None, client)
+            }
+            StartupMode::Mock { game_length } => {
+                let server = MockServer::spawn(MockGame::default().with_game_length(*game_length))?;
+                let client =
+                    Client::connect("127.0.0.1", server.port().into(), self.connect_timeout)?;
+                (None, client)
+            }
+            StartupMode::Playback { path } => (None, Client::playback(path)?),
+            StartupMode::Ladder {
+                server, game_port, ..
+            } => {
+                let client = Client::connect(
+                    &server.to_string(),
+                    (*game_port).into(),
+                    self.connect_timeout,
+                )?;
+                (None, client)
             }
-        }
-        .expect("Failed to start client");
-
-        let player = PlayerSetup {
-            type_: Some(PlayerType::Participant.into()),
-            race: Some(Race::Zerg.into()),
-            player_name: Some("Tomobot".to_owned()),
-            ..Default::default()
         };
 
-        let opponent = PlayerSetup {
-            type_: Some(PlayerType::Computer.into()),
-            race: Some(Race::Terran.into()),
-            difficulty: Some(Difficulty::Medium.into()),
-            ..Default::default()
-        };
+        if let Some(path) = &self.record {
+            info!("Recording game to {}", path.display());
+            client.record_to(path)?;
+        }
+        if let Some(config) = &self.trace {
+            info!("Tracing requests to {}", config.path.display());
+            client.trace_to(config.clone())?;
+        }
 
-        info!("Starting game");
-        client
-            .start_game(
+        if let StartupMode::Replay { path, player_id } = &self.mode {
+            info!("Starting replay {}", path.display());
+            client.start_replay(path, *player_id, self.realtime, self.interface)?;
+
+            return Ok(Session {
+                process,
+                client,
+                player: PlayerId(*player_id),
+                role: Role::Single,
+                observer: false,
+            });
+        }
+
+        // Ladder games are created by the ladder server.
+        let ports = if let StartupMode::Ladder { start_port, .. } = &self.mode {
+            Some(GamePorts::from_start_port(*start_port, 1))
+        } else {
+            info!("Starting game");
+            client.start_game(
                 format!("{}.SC2Map", self.map),
-                player.clone(),
-                opponent,
+                self.setup.player(),
+                self.setup.opponent(),
                 self.realtime,
-            )
-            .expect("Failed to start game");
+                self.setup.seed,
+            )?;
+            None
+        };
 
         info!("Joining game");
-        let bot_id = client.join_game(player).expect("Failed to join game");
+        let bot_id = client.join_game(self.setup.player(), ports.as_ref(), self.interface)?;
+
+        Ok(Session {
+            process,
+            client,
+            player: PlayerId(bot_id),
+            role: Role::Single,
+            observer: false,
+        })
+    }
+}
 
-        app.insert_resource(client);
-        app.insert_resource(PlayerId(bot_id));
-        if let Some(process) = process {
-            app.insert_resource(process);
-        }
+impl Plugin for CorePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CoreError>();
+        app.add_event::<GameResult>();
+        app.add_event::<ChatReceived>();
+        app.add_event::<AlertEvent>();
+        app.add_event::<QueryResult>();
 
         app.init_resource::<Actions>();
         app.init_resource::<DebugCommands>();
+        app.init_resource::<Queries>();
 
         app.init_resource::<ApiMapInfo>();
         app.init_resource::<ApiObservation>();
+        app.init_resource::<ApiActionErrors>();
+        app.init_resource::<ApiFeatureLayers>();
         app.init_resource::<PlayerCommon>();
+        app.init_resource::<Score>();
+        app.init_resource::<GameTime>();
+        app.init_resource::<Metrics>();
+        app.insert_resource(Stepping::new(self.step_count));
+        app.insert_resource(self.interface);
+        if self.realtime {
+            app.insert_resource(Realtime);
+            if self.pipelined {
+                warn!("The game can't be stepped ahead in realtime, pipelining is disabled");
+            }
+        } else if self.pipelined {
+            app.insert_resource(Pipelined);
+        }
 
-        app.add_systems(PreStartup, fetch_game_info);
-        app.add_systems(PreStartup, fetch_world_state);
+        let prepared = self
+            .session
+            .lock()
+            .ok()
+            .and_then(|mut session| session.take());
+        let session = match prepared.map_or_else(|| self.start(), Ok) {
+            Ok(session) => session,
+            Err(error) => {
+                // Request systems are never added and systems needing the game are skipped through
+                // `game_joined`, leaving the error handler to exit the app.
+                app.world_mut().send_event(error);
+                app.add_systems(Last, handle_errors);
+                return;
+            }
+        };
 
-        app.add_systems(First, fetch_world_state);
-        app.add_systems(Last, send_request);
-    }
+        app.insert_resource(session.client);
+        app.insert_resource(session.player);
+        if let Some(process) = session.process {
+            app.insert_resource(process);
+        }
+        let opponent_id = match &self.mode {
+            StartupMode::Ladder { opponent_id, .. } => opponent_id.as_deref(),
+            _ => None,
+        };
+        if let Some(id) = opponent_id {
+            app.insert_resource(OpponentId(id.to_owned()));
+        }
+        if let Some(export) = &self.metrics {
+            app.insert_resource(export.clone());
+        }
+        if let Some(dir) = &self.replay_dir {
+            app.insert_resource(ReplaySaver::new(dir.clone(), &self.setup, opponent_id));
+        }
 
-    fn cleanup(&self, app: &mut App) {
-        if let Some(mut process) = app.world_mut().remove_resource::<Process>() {
-            process.kill().expect("Failed to kill process");
-            process.wait().expect("Failed to wait on process exit");
+        if let StartupMode::Ladder { .. } = self.mode {
+            if self.games > 1 {
+                warn!("Games are restarted by the ladder server, playing a single game");
+            }
+        } else if let StartupMode::Replay { .. } = self.mode {
+            if self.games > 1 {
+                warn!("Replays can't be restarted, observing a single game");
+            }
+            app.insert_resource(Observing);
+        } else {
+            // An observer joins through the opponent's slot of the game.
+            let player = if session.observer {
+                self.setup.opponent()
+            } else {
+                self.setup.player()
+            };
+            app.insert_resource(Restart::new(
+                self.games,
+                player,
+                self.interface,
+                session.role,
+            ));
+        }
+        if session.observer {
+            app.insert_resource(Observing);
         }
+
+        app.add_systems(PreStartup, fetch_game_info.pipe(report_error));
+        // Game data doesn't change between restarted games.
+        app.add_systems(
+            PreStartup,
+            fetch_game_data
+                .pipe(report_error)
+                .run_if(not(resource_exists::<GameData>)),
+        );
+        app.add_systems(PreStartup, fetch_world_state.pipe(report_error));
+
+        signal::install_handler();
+
+        app.add_systems(
+            First,
+            (
+                signal::exit_on_interrupt,
+                fetch_world_state.pipe(report_error),
+                step_ahead
+                    .pipe(report_error)
+                    .run_if(resource_exists::<Pipelined>),
+                ReplaySaver::on_game_end.run_if(resource_exists::<ReplaySaver>),
+            )
+                .chain(),
+        );
+        app.add_systems(
+            Last,
+            (
+                finish_step
+                    .pipe(report_error)
+                    .run_if(resource_exists::<Pipelined>),
+                send_queries.pipe(report_error),
+                send_request.pipe(report_error),
+                ReplaySaver::on_error.run_if(resource_exists::<ReplaySaver>),
+                Metrics::collect_requests,
+                Metrics::export.run_if(resource_exists::<MetricsExport>),
+                Restart::next_game.run_if(Restart::is_due),
+                handle_errors,
+                shutdown,
+            )
+                .chain(),
+        );
     }
 }
 
 #[derive(Resource, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
 struct PlayerId(u32);
 
+/// Identifier of the opponent given by the ladder server, if any.
+#[derive(Resource, Default, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct OpponentId(String);
+
+impl std::ops::Deref for OpponentId {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Sent when a game ends.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameResult {
+    pub outcome: Outcome,
+    /// Length of the game in game loops.
+    pub game_loop: u32,
+    pub score: i32,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Victory,
+    Defeat,
+    Tie,
+    /// The game didn't report a result for us.
+    Undecided,
+}
+
+impl From<sc2api::Result> for Outcome {
+    fn from(value: sc2api::Result) -> Self {
+        match value {
+            sc2api::Result::Victory => Self::Victory,
+            sc2api::Result::Defeat => Self::Defeat,
+            sc2api::Result::Tie => Self::Tie,
+            sc2api::Result::Undecided => Self::Undecided,
+        }
+    }
+}
+
 /// Observation provided by the game API.
 ///
 /// This contains things like visibile units, effects and events. It is stored as a resource in the
@@ -140,6 +499,44 @@ impl std::ops::Deref for ApiObservation {
     }
 }
 
+/// Errors of the actions sent before the most recent observation, including any sent while the app
+/// was asleep.
+#[derive(Resource, Default, Clone, Debug, PartialEq)]
+pub struct ApiActionErrors(Vec<sc2api::ActionError>);
+
+impl std::ops::Deref for ApiActionErrors {
+    type Target = [sc2api::ActionError];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Marks that the game runs in realtime rather than being stepped.
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+struct Realtime;
+
+/// Marks that the game is stepped during updates rather than between them.
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+struct Pipelined;
+
+/// Marks that a replay or game is being observed rather than played.
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+struct Observing;
+
+/// Feature layers of the most recent observation. Only observed if enabled through
+/// [`InterfaceConfig::feature_layers`].
+#[derive(Resource, Default, Clone, Debug, PartialEq)]
+pub struct ApiFeatureLayers(sc2_proto::spatial::ObservationFeatureLayer);
+
+impl std::ops::Deref for ApiFeatureLayers {
+    type Target = sc2_proto::spatial::ObservationFeatureLayer;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 #[derive(Resource, Default, Clone, Debug, PartialEq)]
 pub struct PlayerCommon(sc2_proto::sc2api::PlayerCommon);
 
@@ -151,6 +548,18 @@ impl std::ops::Deref for PlayerCommon {
     }
 }
 
+/// Score of the game so far, including the full [`ScoreDetails`](sc2_proto::score::ScoreDetails).
+#[derive(Resource, Default, Clone, Debug, PartialEq)]
+pub struct Score(sc2_proto::score::Score);
+
+impl std::ops::Deref for Score {
+    type Target = sc2_proto::score::Score;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 #[derive(Resource, Default, Clone, Debug, PartialEq)]
 pub struct ApiMapInfo(sc2_proto::raw::StartRaw);
 
@@ -162,116 +571,387 @@ impl std::ops::Deref for ApiMapInfo {
     }
 }
 
-fn fetch_game_info(mut client: ResMut<Client>, mut api_map: ResMut<ApiMapInfo>) {
+/// Run condition for systems which need the state of a game. Nothing is fetched from the game if
+/// startup failed, in which case the app exits after its first update.
+pub fn game_joined(client: Option<Res<Client>>) -> bool {
+    client.is_some()
+}
+
+/// Forward errors from core systems as [`CoreError`] events.
+fn report_error(In(result): In<Result<(), CoreError>>, mut errors: EventWriter<CoreError>) {
+    if let Err(error) = result {
+        errors.send(error);
+    }
+}
+
+/// Log errors from core systems and exit the app.
+fn handle_errors(mut errors: EventReader<CoreError>, mut exit: EventWriter<AppExit>) {
+    for error in errors.read() {
+        if let CoreError::GameEnded = error {
+            info!("{error}");
+            exit.send(AppExit::Success);
+        } else {
+            error!("{error}");
+            exit.send(AppExit::error());
+        }
+    }
+}
+
+/// Leave the game once the app is exiting, then close the game if it was launched by the app.
+fn shutdown(
+    exits: EventReader<AppExit>,
+    client: Option<ResMut<Client>>,
+    process: Option<ResMut<Process>>,
+) {
+    if exits.is_empty() {
+        return;
+    }
+
+    let Some(mut client) = client else {
+        return;
+    };
+
+    if let Err(e) = client.leave_game() {
+        warn!("Failed to leave game: {e}");
+    }
+
+    let Some(mut process) = process else {
+        return;
+    };
+
+    info!("Closing game");
+    if let Err(e) = client.quit() {
+        warn!("Failed to ask game to quit: {e}");
+    }
+    if let Err(e) = process.stop(Process::QUIT_TIMEOUT) {
+        error!("Failed to stop game process: {e}");
+    }
+}
+
+fn fetch_game_info(
+    mut client: ResMut<Client>,
+    interface: Res<InterfaceConfig>,
+    mut api_map: ResMut<ApiMapInfo>,
+    replays: Option<ResMut<ReplaySaver>>,
+) -> Result<(), CoreError> {
     let request = {
         let mut request = Request::new();
         request.mut_game_info();
         request
     };
 
-    let mut response = client.send(request).inspect_err(|e| error!("{e}")).unwrap();
+    let mut response = client.send(request)?;
+
+    if let Some(mut replays) = replays {
+        replays.set_map(response.game_info().map_name());
+    }
 
     let ResponseGameInfo {
         start_raw: MessageField(Some(start_raw)),
         ..
     } = response.take_game_info()
     else {
-        error!("Api response contains unexpected pattern");
-        return;
+        return Err(CoreError::UnexpectedResponse("game_info.start_raw"));
     };
 
-    *api_map = ApiMapInfo(*start_raw);
+    let mut start_raw = *start_raw;
+    if interface.crop_to_playable_area {
+        interface::crop_to_playable_area(&mut start_raw);
+    }
+
+    *api_map = ApiMapInfo(start_raw);
+    Ok(())
+}
+
+fn fetch_game_data(mut commands: Commands, mut client: ResMut<Client>) -> Result<(), CoreError> {
+    let request = {
+        let mut request = Request::new();
+        let data = request.mut_data();
+        data.set_unit_type_id(true);
+        data.set_ability_id(true);
+        data.set_upgrade_id(true);
+        data.set_buff_id(true);
+        data.set_effect_id(true);
+        request
+    };
+
+    let mut response = client.send(request)?;
+    if !response.has_data() {
+        return Err(CoreError::UnexpectedResponse("data"));
+    }
+
+    commands.insert_resource(GameData::from(response.take_data()));
+    Ok(())
 }
 
 fn fetch_world_state(
     player: Res<PlayerId>,
+    interface: Res<InterfaceConfig>,
+    realtime: Option<Res<Realtime>>,
     mut client: ResMut<Client>,
     mut api_observation: ResMut<ApiObservation>,
+    mut feature_layers: ResMut<ApiFeatureLayers>,
     mut player_resources: ResMut<PlayerCommon>,
+    mut score: ResMut<Score>,
+    mut time: ResMut<GameTime>,
+    mut stepping: ResMut<Stepping>,
+    restart: Option<Res<Restart>>,
+    mut results: EventWriter<GameResult>,
+    mut action_errors: ResMut<ApiActionErrors>,
+    mut chat: EventWriter<ChatReceived>,
+    mut alerts: EventWriter<AlertEvent>,
     mut exit: EventWriter<AppExit>,
-) {
-    let request = {
-        let mut request = Request::new();
-        request.mut_observation().set_disable_fog(false);
-        request
+) -> Result<(), CoreError> {
+    let mut errors = Vec::new();
+    let mut messages = Vec::new();
+    let mut observed_alerts = Vec::new();
+    let mut last_loop = time.game_loop();
+    let mut previous = None;
+
+    // While the app is asleep the game is stepped on without updating the app. Events along the
+    // way are kept until it's woken.
+    let (observation, player_results, ended) = loop {
+        let request = observation_request(&interface, realtime.is_some(), last_loop);
+        let mut response = client.send(request)?;
+        let ended = response.status() == Status::ended;
+
+        let ResponseObservation {
+            actions: _,
+            action_errors: new_errors,
+            observation: MessageField(Some(observation)),
+            player_result,
+            chat: new_messages,
+            ..
+        } = response.take_observation()
+        else {
+            return Err(CoreError::UnexpectedResponse("observation"));
+        };
+
+        errors.extend(new_errors);
+        messages.extend(new_messages);
+        observed_alerts.extend(observation.alerts.clone());
+        last_loop = observation.game_loop();
+
+        let previous_raw = previous.as_ref().unwrap_or(&api_observation.0);
+        if ended || stepping.wakes(&observation, previous_raw, time.game_loop()) {
+            break (observation, player_result, ended);
+        }
+
+        // Units are compared against the previous step rather than the last update, so damage
+        // isn't hidden by shields regenerating over a long sleep.
+        previous = observation.raw_data.into_option();
+
+        if realtime.is_none() {
+            step(&mut client, stepping.count())?;
+        }
+        client.prefetch(observation_request(
+            &interface,
+            realtime.is_some(),
+            last_loop,
+        ))?;
     };
 
-    let mut response = client.send(request).inspect_err(|e| error!("{e}")).unwrap();
+    if ended {
+        let outcome = player_results
+            .iter()
+            .find(|result| result.player_id() == player.0)
+            .map_or(Outcome::Undecided, |result| result.result().into());
 
-    if matches!(response.status(), Status::ended) {
-        let result = response.observation().player_result[player.0 as usize - 1].result();
+        let result = GameResult {
+            outcome,
+            game_loop: observation.game_loop(),
+            score: observation.score.score(),
+        };
         info!("Game finished. Result: {:?}", result);
-        exit.send(AppExit::Success);
+        results.send(result);
+        if !Restart::pending(restart) {
+            exit.send(AppExit::Success);
+        }
     }
 
-    let ResponseObservation {
-        actions: _,
-        action_errors: _,
-        observation: MessageField(Some(observation)),
-        chat: _,
-        ..
-    } = response.take_observation()
-    else {
-        error!("Api response contains unexpected pattern");
-        return;
-    };
-
     let sc2api::Observation {
-        game_loop: _,
+        game_loop,
         player_common: MessageField(Some(player)),
         alerts: _,
         abilities: _,
-        score: _,
+        score: new_score,
         raw_data: MessageField(Some(observation)),
+        feature_layer_data: layers,
         ..
     } = *observation
     else {
-        error!("Observation contains unexpected pattern");
-        return;
+        return Err(CoreError::UnexpectedResponse(
+            "observation.player_common or observation.raw_data",
+        ));
     };
 
     *api_observation = ApiObservation(*observation);
     *player_resources = PlayerCommon(*player);
+    time.update(game_loop.unwrap_or_default());
+    if let MessageField(Some(new_score)) = new_score {
+        *score = Score(*new_score);
+    }
+    if let MessageField(Some(layers)) = layers {
+        *feature_layers = ApiFeatureLayers(*layers);
+    }
+
+    *action_errors = ApiActionErrors(errors);
+    chat.send_batch(messages.into_iter().map(ChatReceived::from));
+    alerts.send_batch(
+        observed_alerts
+            .into_iter()
+            .filter_map(|alert| alert.enum_value().ok())
+            .map(AlertEvent),
+    );
+    Ok(())
+}
+
+/// Step the game and request the next observation as soon as the current one has been fetched, so
+/// the game runs while the app updates.
+fn step_ahead(
+    mut client: ResMut<Client>,
+    interface: Res<InterfaceConfig>,
+    time: Res<GameTime>,
+    stepping: Res<Stepping>,
+) -> Result<(), CoreError> {
+    if client.status() == Status::ended {
+        return Ok(());
+    }
+
+    step(&mut client, stepping.count())?;
+    client.prefetch(observation_request(&interface, false, time.game_loop()))
+}
+
+/// Wait for the step started by [`step_ahead`], as the game may have ended during it.
+fn finish_step(mut client: ResMut<Client>) -> Result<(), CoreError> {
+    client.wait()
+}
+
+fn send_queries(
+    mut client: ResMut<Client>,
+    mut queries: ResMut<Queries>,
+    mut results: EventWriter<QueryResult>,
+) -> Result<(), CoreError> {
+    if queries.is_empty() || client.status() == Status::ended {
+        return Ok(());
+    }
+
+    for (query, ids) in queries.take_requests() {
+        let request = {
+            let mut request = Request::new();
+            request.set_query(query);
+            request
+        };
+
+        let mut response = client.send(request)?;
+        results.send_batch(query::results(&ids, response.take_query())?);
+    }
+    Ok(())
+}
+
+fn observation_request(interface: &InterfaceConfig, realtime: bool, last_loop: u32) -> Request {
+    let mut request = Request::new();
+    let observation = request.mut_observation();
+    observation.set_disable_fog(interface.disable_fog);
+
+    // The game runs on by itself in realtime. Rather than observing the same game loop again if
+    // the app is quicker than the game, wait for the next one.
+    if realtime {
+        observation.set_game_loop(last_loop + 1);
+    }
+    request
 }
 
 fn send_request(
     mut client: ResMut<Client>,
     mut actions: ResMut<Actions>,
     mut commands: ResMut<DebugCommands>,
-) {
+    interface: Res<InterfaceConfig>,
+    time: Res<GameTime>,
+    observing: Option<Res<Observing>>,
+    realtime: Option<Res<Realtime>>,
+    pipelined: Option<Res<Pipelined>>,
+    stepping: Res<Stepping>,
+) -> Result<(), CoreError> {
+    // Nothing can be sent once the game has ended. The app is either exiting or restarting.
+    if client.status() == Status::ended {
+        return Ok(());
+    }
+
+    // The game rejects actions and debug commands from an observer, so the same systems can run
+    // over a replay without their output ever being sent.
+    if observing.is_some() {
+        actions.clear();
+        commands.clear();
+    } else {
+        send_actions(&mut client, &mut actions, &mut commands)?;
+    }
+
+    // The game has already been stepped on by `step_ahead`.
+    if pipelined.is_some() {
+        return Ok(());
+    }
+
+    // The game rejects steps in realtime.
+    if realtime.is_none() {
+        step(&mut client, stepping.count())?;
+    }
+
+    // The next observation is requested straight away so the game steps and the response is
+    // decoded in the background between updates, rather than once the next update starts.
+    client.prefetch(observation_request(
+        &interface,
+        realtime.is_some(),
+        time.game_loop(),
+    ))
+}
+
+fn step(client: &mut Client, count: u32) -> Result<(), CoreError> {
     let request = {
         let mut complete_request = Request::new();
 
-        let request = &mut complete_request.mut_action();
-        request.actions.append(&mut actions);
+        let request = complete_request.mut_step();
+        request.set_count(count);
 
         complete_request
     };
 
-    let _response = client.send(request).inspect_err(|e| error!("{e}")).unwrap();
+    // The response is checked by the next request sent, after which the game has stepped.
+    client.submit(request)
+}
 
+fn send_actions(
+    client: &mut Client,
+    actions: &mut Actions,
+    commands: &mut DebugCommands,
+) -> Result<(), CoreError> {
     let request = {
         let mut complete_request = Request::new();
 
-        let request = &mut complete_request.mut_debug().debug;
-        request.append(&mut commands);
+        let request = &mut complete_request.mut_action();
+        request.actions.append(actions);
 
         complete_request
     };
 
-    let response = client.send(request).inspect_err(|e| error!("{e}")).unwrap();
-    for error in &response.error {
-        error!(error);
-    }
+    let _response = client.send(request)?;
 
     let request = {
         let mut complete_request = Request::new();
 
-        let request = complete_request.mut_step();
-        request.set_count(1);
+        let request = &mut complete_request.mut_debug().debug;
+        request.append(commands);
 
         complete_request
     };
 
-    let _response = client.send(request).inspect_err(|e| error!("{e}")).unwrap();
+    // Failed debug commands aren't fatal.
+    match client.send(request) {
+        Err(CoreError::Api(errors)) => errors.iter().for_each(|e| error!("{e}")),
+        result => {
+            result?;
+        }
+    }
+
+    Ok(())
 }
@@ -2,12 +2,14 @@ use std::{
     net::TcpListener,
     path::Path,
     process::{Child, Command, ExitStatus},
+    time::{Duration, Instant},
 };
 
 use bevy::ecs::system::Resource;
 use regex::Regex;
+use tracing::{info, warn};
 
-use super::client::Client;
+use super::{client::Client, error::CoreError};
 
 const SC2_BINARY: &str = "SC2_x64.exe";
 const SC2_SUPPORT: &str = "Support64";
@@ -33,21 +35,40 @@ pub struct Process(Child);
 
 impl Drop for Process {
     fn drop(&mut self) {
-        self.0.kill().expect("Failed to kill process");
+        // The process may already have been stopped.
+        if let Ok(Some(_)) = self.0.try_wait() {
+            return;
+        }
+
+        if let Err(e) = self.0.kill().and_then(|_| self.0.wait()) {
+            warn!("Failed to kill game process: {e}");
+        }
     }
 }
 
 impl Process {
-    pub fn wait(&mut self) -> std::io::Result<ExitStatus> {
-        self.0.wait()
-    }
+    /// Time given for the game to close after being asked to quit.
+    pub const QUIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Wait up to `timeout` for the process to exit by itself, killing it if it hasn't.
+    pub fn stop(&mut self, timeout: Duration) -> std::io::Result<ExitStatus> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(status) = self.0.try_wait()? {
+                return Ok(status);
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
 
-    pub fn kill(&mut self) -> std::io::Result<()> {
-        self.0.kill()
+        warn!("Game process didn't exit within {timeout:?}, killing it");
+        self.0.kill()?;
+        self.0.wait()
     }
 }
 
-pub fn launch_client() -> Result<(Process, Client), anyhow::Error> {
+/// Launch the game and connect to it, giving up if the game hasn't opened its port within
+/// `timeout`.
+pub fn launch_client(timeout: Duration) -> Result<(Process, Client), CoreError> {
     let sc2_path = get_path_to_sc2();
     let (base_version, data_hash) = (get_latest_base_version(&sc2_path), "");
 
@@ -72,7 +93,12 @@ pub fn launch_client() -> Result<(Process, Client), anyhow::Error> {
     }
 
     let process = process.spawn().map(Process)?;
-    let client = Client::connect("127.0.0.1", port)?;
+    info!(
+        "Launched game process {}, waiting for it to listen on port {port}",
+        process.0.id()
+    );
+
+    let client = Client::connect(HOST, port, timeout)?;
     Ok((process, client))
 }
 
@@ -114,6 +140,12 @@ fn get_latest_base_version(sc2_path: &str) -> u32 {
         .unwrap()
 }
 
+/// Find the first of `count` consecutive unused ports.
+pub fn get_unused_port_range(count: u16) -> Option<u16> {
+    (5000..65535 - count)
+        .find(|&start| (start..start + count).all(|port| TcpListener::bind((HOST, port)).is_ok()))
+}
+
 fn get_unused_port() -> i32 {
     (5000..65535)
         .find(|port| TcpListener::bind((HOST, *port)).is_ok())
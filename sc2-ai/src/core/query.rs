@@ -0,0 +1,211 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bevy::ecs::{event::Event, system::Resource};
+use num_traits::FromPrimitive as _;
+
+use sc2_proto::{
+    AbilityId,
+    error::ActionResult,
+    query::{
+        RequestQuery, RequestQueryAvailableAbilities, RequestQueryBuildingPlacement,
+        RequestQueryPathing, ResponseQuery,
+    },
+};
+
+use super::error::CoreError;
+
+/// Identifies the [`QueryResult`] of a query.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct QueryId(u64);
+
+impl QueryId {
+    /// Allocate a new ID. IDs are allocated up front so they can be returned by deferred commands.
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Queries to send to the game at the end of the tick, before stepping the game.
+///
+/// Results are returned as [`QueryResult`] events.
+#[derive(Resource, Default, Clone, Debug, PartialEq)]
+pub struct Queries {
+    pathing: Vec<(QueryId, RequestQueryPathing)>,
+    placements: Vec<(QueryId, RequestQueryBuildingPlacement)>,
+    abilities: Vec<(QueryId, RequestQueryAvailableAbilities)>,
+}
+
+#[derive(Event, Clone, Debug, PartialEq)]
+pub struct QueryResult {
+    pub id: QueryId,
+    pub response: QueryResponse,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueryResponse {
+    /// Distance along the shortest path, or [`None`] if there is no path.
+    Pathing(Option<f32>),
+    /// [`ActionResult::Success`] if the building can be placed.
+    Placement(ActionResult),
+    Abilities(Box<[AbilityId]>),
+}
+
+impl Queries {
+    pub fn pathing(&mut self, id: QueryId, query: RequestQueryPathing) {
+        self.pathing.push((id, query));
+    }
+
+    pub fn placement(&mut self, id: QueryId, query: RequestQueryBuildingPlacement) {
+        self.placements.push((id, query));
+    }
+
+    pub fn abilities(&mut self, id: QueryId, query: RequestQueryAvailableAbilities) {
+        self.abilities.push((id, query));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pathing.is_empty() && self.placements.is_empty() && self.abilities.is_empty()
+    }
+
+    /// Batch the queries into requests, each with the IDs of its queries in the order their
+    /// results are returned.
+    ///
+    /// Pathing and placement queries are sent together, ignoring resource requirements so that
+    /// only the placement itself is checked. Ability queries are sent separately, so that they
+    /// only return abilities the unit can afford.
+    pub(super) fn take_requests(&mut self) -> Vec<(RequestQuery, Box<[QueryId]>)> {
+        let pathing = std::mem::take(&mut self.pathing);
+        let placements = std::mem::take(&mut self.placements);
+        let abilities = std::mem::take(&mut self.abilities);
+        let mut requests = Vec::new();
+
+        if !pathing.is_empty() || !placements.is_empty() {
+            let ids = pathing
+                .iter()
+                .map(|(id, _)| *id)
+                .chain(placements.iter().map(|(id, _)| *id))
+                .collect();
+
+            let request = RequestQuery {
+                pathing: pathing.into_iter().map(|(_, query)| query).collect(),
+                placements: placements.into_iter().map(|(_, query)| query).collect(),
+                ignore_resource_requirements: Some(true),
+                ..Default::default()
+            };
+            requests.push((request, ids));
+        }
+
+        if !abilities.is_empty() {
+            let ids = abilities.iter().map(|(id, _)| *id).collect();
+
+            let request = RequestQuery {
+                abilities: abilities.into_iter().map(|(_, query)| query).collect(),
+                ignore_resource_requirements: Some(false),
+                ..Default::default()
+            };
+            requests.push((request, ids));
+        }
+
+        requests
+    }
+}
+
+/// Pair the results of a batched query with the IDs returned by [`Queries::take_requests`].
+///
+/// Every query must have a result, as those without one would otherwise never be answered.
+pub(super) fn results(
+    ids: &[QueryId],
+    response: ResponseQuery,
+) -> Result<Vec<QueryResult>, CoreError> {
+    let received = response.pathing.len() + response.placements.len() + response.abilities.len();
+    if received != ids.len() {
+        return Err(CoreError::QueryResults {
+            expected: ids.len(),
+            received,
+        });
+    }
+
+    let pathing = response.pathing.into_iter().map(|path| {
+        // The game reports a distance of zero if there is no path.
+        let distance = path.distance();
+        QueryResponse::Pathing((distance > 0.0).then_some(distance))
+    });
+
+    let placements = response
+        .placements
+        .into_iter()
+        .map(|placement| QueryResponse::Placement(placement.result()));
+
+    let abilities = response.abilities.into_iter().map(|abilities| {
+        let abilities = abilities
+            .abilities
+            .iter()
+            .filter_map(|ability| AbilityId::from_i32(ability.ability_id()))
+            .collect();
+        QueryResponse::Abilities(abilities)
+    });
+
+    Ok(ids
+        .iter()
+        .zip(pathing.chain(placements).chain(abilities))
+        .map(|(&id, response)| QueryResult { id, response })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use sc2_proto::query::{ResponseQueryAvailableAbilities, ResponseQueryPathing};
+
+    use super::*;
+
+    #[test]
+    fn sends_ability_queries_separately() {
+        let mut queries = Queries::default();
+        let (path, placement, abilities) = (QueryId::next(), QueryId::next(), QueryId::next());
+        queries.abilities(abilities, RequestQueryAvailableAbilities::new());
+        queries.placement(placement, RequestQueryBuildingPlacement::new());
+        queries.pathing(path, RequestQueryPathing::new());
+
+        let requests = queries.take_requests();
+        assert!(queries.is_empty());
+        assert_eq!(requests.len(), 2);
+
+        let (request, ids) = &requests[0];
+        assert_eq!(**ids, [path, placement]);
+        assert!(request.abilities.is_empty());
+        assert_eq!(request.ignore_resource_requirements, Some(true));
+
+        let (request, ids) = &requests[1];
+        assert_eq!(**ids, [abilities]);
+        assert_eq!(request.abilities.len(), 1);
+        assert_eq!(request.ignore_resource_requirements, Some(false));
+    }
+
+    #[test]
+    fn reports_missing_results() {
+        let ids = [QueryId::next(), QueryId::next()];
+        let response = ResponseQuery {
+            pathing: vec![ResponseQueryPathing::default()],
+            ..Default::default()
+        };
+
+        let missing = results(&ids, response);
+        assert!(matches!(
+            missing,
+            Err(CoreError::QueryResults {
+                expected: 2,
+                received: 1
+            })
+        ));
+
+        let response = ResponseQuery {
+            pathing: vec![ResponseQueryPathing::default()],
+            abilities: vec![ResponseQueryAvailableAbilities::default()],
+            ..Default::default()
+        };
+        let results = results(&ids, response).unwrap();
+        assert_eq!(results[0].response, QueryResponse::Pathing(None));
+        assert_eq!(results[1].response, QueryResponse::Abilities(Box::new([])));
+    }
+}
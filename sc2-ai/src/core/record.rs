@@ -0,0 +1,155 @@
+//! Recording and playback of the raw request/response stream of a game.
+//!
+//! A recording is a sequence of entries, each being the game loop the request was sent on as a
+//! varint, followed by the length delimited [`Request`] and [`Response`].
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    mem::Discriminant,
+    path::Path,
+};
+
+use protobuf::{CodedInputStream, CodedOutputStream};
+
+use sc2_proto::sc2api::{Request, Response, request::Request as ApiRequest};
+
+use super::error::CoreError;
+
+#[derive(Debug)]
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn record(
+        &mut self,
+        game_loop: u32,
+        request: &Request,
+        response: &Response,
+    ) -> Result<(), protobuf::Error> {
+        let mut stream = CodedOutputStream::new(&mut self.writer);
+        stream.write_raw_varint32(game_loop)?;
+        stream.write_message_no_tag(request)?;
+        stream.write_message_no_tag(response)?;
+        stream.flush()
+    }
+}
+
+/// Serves responses from a recording in place of the game.
+#[derive(Debug)]
+pub struct Playback {
+    reader: BufReader<File>,
+    entry: usize,
+}
+
+impl Playback {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(File::open(path)?),
+            entry: 0,
+        })
+    }
+
+    /// Number of entries played back so far.
+    pub fn entries(&self) -> usize {
+        self.entry
+    }
+
+    /// Return the recorded response to the next request.
+    ///
+    /// The request must be of the same kind as the one recorded. Its contents may differ, allowing
+    /// modified systems to be run against a recorded game.
+    pub fn respond(&mut self, request: &Request) -> Result<Response, CoreError> {
+        let mut stream = CodedInputStream::from_buf_read(&mut self.reader);
+        if stream.eof()? {
+            return Err(CoreError::RecordingEnded(self.entry));
+        }
+
+        let game_loop = stream.read_raw_varint32()?;
+        let recorded = stream.read_message::<Request>()?;
+        let response = stream.read_message::<Response>()?;
+        self.entry += 1;
+
+        if kind(&recorded) != kind(request) {
+            return Err(CoreError::RecordingDiverged {
+                entry: self.entry,
+                game_loop,
+            });
+        }
+
+        Ok(response)
+    }
+}
+
+fn kind(request: &Request) -> Option<Discriminant<ApiRequest>> {
+    request.request.as_ref().map(std::mem::discriminant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("sc2-ai-{}-{name}", std::process::id()))
+    }
+
+    fn ping() -> Request {
+        let mut request = Request::new();
+        request.mut_ping();
+        request
+    }
+
+    fn step() -> Request {
+        let mut request = Request::new();
+        request.mut_step().set_count(1);
+        request
+    }
+
+    #[test]
+    fn plays_back_until_requests_diverge() {
+        let path = temp_path("playback");
+        {
+            let mut recorder = Recorder::create(&path).unwrap();
+            let mut response = Response::new();
+            response.mut_ping().set_game_version("1.0".to_owned());
+            recorder.record(0, &ping(), &response).unwrap();
+            recorder.record(0, &ping(), &Response::new()).unwrap();
+        }
+
+        let mut playback = Playback::open(&path).unwrap();
+        let response = playback.respond(&ping()).unwrap();
+        assert_eq!(response.ping().game_version(), "1.0");
+
+        let diverged = playback.respond(&step());
+        assert!(matches!(
+            diverged,
+            Err(CoreError::RecordingDiverged { entry: 2, .. })
+        ));
+
+        let ended = playback.respond(&ping());
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(ended, Err(CoreError::RecordingEnded(2))));
+    }
+
+    #[test]
+    fn reports_truncated_recording() {
+        let path = temp_path("truncated");
+        {
+            let mut recorder = Recorder::create(&path).unwrap();
+            recorder.record(0, &step(), &Response::new()).unwrap();
+        }
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+
+        let result = Playback::open(&path).unwrap().respond(&step());
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(result, Err(CoreError::Protocol(_))));
+    }
+}
@@ -0,0 +1,103 @@
+//! Saving replays of games once they end, or when the app exits early.
+
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::ecs::{
+    event::EventReader,
+    system::{Res, ResMut, Resource},
+};
+use tracing::{error, info};
+
+use super::{CoreError, GameResult, GameSetup, OpponentType, client::Client};
+
+/// Saves replays to a directory, named after the map, opponent, result and time of the game.
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub(super) struct ReplaySaver {
+    dir: PathBuf,
+    map: String,
+    opponent: String,
+}
+
+impl ReplaySaver {
+    pub(super) fn new(dir: PathBuf, setup: &GameSetup, opponent_id: Option<&str>) -> Self {
+        let opponent = match (opponent_id, setup.opponent) {
+            (Some(id), _) => id.to_owned(),
+            (None, OpponentType::Computer) => {
+                format!("{:?}{:?}", setup.opponent_race, setup.difficulty)
+            }
+            (None, OpponentType::Participant) => {
+                format!("{:?}", setup.opponent_race)
+            }
+            (None, OpponentType::Observer) => "NoOpponent".to_owned(),
+        };
+
+        Self {
+            dir,
+            map: String::new(),
+            opponent,
+        }
+    }
+
+    /// Set the name of the map being played, as reported by the game.
+    pub(super) fn set_map(&mut self, map: &str) {
+        self.map = map.to_owned();
+    }
+
+    /// Save a replay of the current game, logging rather than returning any failure as the game
+    /// is already over.
+    pub(super) fn save(&self, client: &mut Client, result: &str) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+
+        let name = format!("{timestamp}_{}_{}_{result}", self.map, self.opponent);
+        let name = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+            .collect::<String>();
+        let path = self.dir.join(name).with_extension("SC2Replay");
+
+        let saved = std::fs::create_dir_all(&self.dir)
+            .map_err(CoreError::from)
+            .and_then(|_| client.save_replay(&path));
+
+        match saved {
+            Ok(()) => info!("Saved replay to {}", path.display()),
+            Err(e) => error!("Failed to save replay: {e}"),
+        }
+    }
+
+    /// Save a replay once the game has ended.
+    pub(super) fn on_game_end(
+        mut results: EventReader<GameResult>,
+        mut client: ResMut<Client>,
+        saver: Res<Self>,
+    ) {
+        for result in results.read() {
+            saver.save(&mut client, &format!("{:?}", result.outcome));
+        }
+    }
+
+    /// Save a replay before exiting on an error. There's no point trying if the connection to the
+    /// game has failed.
+    pub(super) fn on_error(
+        mut errors: EventReader<CoreError>,
+        client: Option<ResMut<Client>>,
+        saver: Res<Self>,
+    ) {
+        let fatal = errors.read().any(|error| {
+            !matches!(
+                error,
+                CoreError::GameEnded | CoreError::Transport(_) | CoreError::ConnectTimeout { .. }
+            )
+        });
+
+        if let (true, Some(mut client)) = (fatal, client) {
+            saver.save(&mut client, "Error");
+        }
+    }
+}
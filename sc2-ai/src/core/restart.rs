@@ -0,0 +1,160 @@
+//! Playing several games in a row within the same game instances.
+
+use std::sync::{Arc, Barrier};
+
+use bevy::{
+    app::MainScheduleOrder,
+    ecs::{
+        event::EventReader,
+        system::{Res, Resource},
+        world::{Mut, World},
+    },
+};
+use tracing::info;
+
+use sc2_proto::sc2api::PlayerSetup;
+
+use super::{
+    Actions, DebugCommands, GameResult, GameTime, PlayerId, Queries, Stepping,
+    client::{Client, GamePorts},
+    error::CoreError,
+    interface::InterfaceConfig,
+};
+
+/// Starts the next game once the current one has ended.
+#[derive(Resource, Debug)]
+pub(super) struct Restart {
+    games_left: u32,
+    player: PlayerSetup,
+    interface: InterfaceConfig,
+    role: Role,
+}
+
+/// How a session takes part in starting the next game.
+#[derive(Debug)]
+pub(super) enum Role {
+    /// Single player games are restarted in place.
+    Single,
+
+    /// Multiplayer games are left by every player then created again by the host. Players rejoin
+    /// once the game has been created.
+    Host {
+        map: String,
+        opponent: PlayerSetup,
+        realtime: bool,
+        seed: Option<u32>,
+        ports: GamePorts,
+        created: Arc<Barrier>,
+    },
+    Guest {
+        ports: GamePorts,
+        created: Arc<Barrier>,
+    },
+}
+
+impl Restart {
+    pub(super) fn new(
+        games: u32,
+        player: PlayerSetup,
+        interface: InterfaceConfig,
+        role: Role,
+    ) -> Self {
+        Self {
+            games_left: games.saturating_sub(1),
+            player,
+            interface,
+            role,
+        }
+    }
+
+    /// Whether there's another game to play once the current one ends.
+    pub(super) fn pending(restart: Option<Res<Self>>) -> bool {
+        restart.is_some_and(|restart| restart.games_left > 0)
+    }
+
+    /// Run condition for [`Restart::next_game`], once the end of the game has been observed.
+    ///
+    /// The status of the client isn't enough when the game is stepped during updates, as the
+    /// response to the step may show the game has ended before the observation of its result.
+    pub(super) fn is_due(mut results: EventReader<GameResult>, restart: Option<Res<Self>>) -> bool {
+        results.read().count() > 0 && Self::pending(restart)
+    }
+
+    /// Start the next game then reset the world by despawning every entity and running the
+    /// startup schedules again.
+    pub(super) fn next_game(world: &mut World) {
+        let result = world.resource_scope(|world, mut restart: Mut<Self>| {
+            restart.games_left -= 1;
+            info!("Starting next game, {} left", restart.games_left);
+
+            let player = restart.start(&mut world.resource_mut::<Client>())?;
+            if let Some(player) = player {
+                *world.resource_mut::<PlayerId>() = PlayerId(player);
+            }
+            Ok::<_, CoreError>(())
+        });
+
+        if let Err(error) = result {
+            world.send_event(error);
+            return;
+        }
+
+        world.clear_entities();
+        world.insert_resource(Actions::default());
+        world.insert_resource(DebugCommands::default());
+        world.insert_resource(Queries::default());
+        world.insert_resource(GameTime::default());
+        world.resource_mut::<Stepping>().wake();
+
+        world.resource_scope(|world, order: Mut<MainScheduleOrder>| {
+            for &label in &order.startup_labels {
+                let _ = world.try_run_schedule(label);
+            }
+        });
+    }
+
+    /// Start the next game, returning the new player ID if it changed.
+    fn start(&self, client: &mut Client) -> Result<Option<u32>, CoreError> {
+        match &self.role {
+            Role::Single => {
+                client.restart_game()?;
+                Ok(None)
+            }
+            Role::Host {
+                map,
+                opponent,
+                realtime,
+                seed,
+                ports,
+                created,
+            } => {
+                let result = client.leave_game().and_then(|_| {
+                    client.start_game(
+                        map.clone(),
+                        self.player.clone(),
+                        opponent.clone(),
+                        *realtime,
+                        *seed,
+                    )
+                });
+
+                // The guest waits on the game being created, so must be released even on failure.
+                created.wait();
+                result?;
+
+                client
+                    .join_game(self.player.clone(), Some(ports), self.interface)
+                    .map(Some)
+            }
+            Role::Guest { ports, created } => {
+                let result = client.leave_game();
+                created.wait();
+                result?;
+
+                client
+                    .join_game(self.player.clone(), Some(ports), self.interface)
+                    .map(Some)
+            }
+        }
+    }
+}
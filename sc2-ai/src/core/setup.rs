@@ -0,0 +1,168 @@
+//! Player and opponent configuration used when creating a game.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use sc2_proto::{common, sc2api};
+
+#[derive(ValueEnum, Serialize, Deserialize, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Race {
+    Terran,
+    #[default]
+    Zerg,
+    Protoss,
+    Random,
+}
+
+impl From<Race> for common::Race {
+    fn from(value: Race) -> Self {
+        match value {
+            Race::Terran => Self::Terran,
+            Race::Zerg => Self::Zerg,
+            Race::Protoss => Self::Protoss,
+            Race::Random => Self::Random,
+        }
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OpponentType {
+    /// The game's built-in AI.
+    #[default]
+    Computer,
+    /// Another instance of this bot.
+    Participant,
+    /// No opponent. Another instance of this bot observes the game instead, without acting.
+    Observer,
+}
+
+impl From<OpponentType> for sc2api::PlayerType {
+    fn from(value: OpponentType) -> Self {
+        match value {
+            OpponentType::Computer => Self::Computer,
+            OpponentType::Participant => Self::Participant,
+            OpponentType::Observer => Self::Observer,
+        }
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Difficulty {
+    VeryEasy,
+    Easy,
+    #[default]
+    Medium,
+    MediumHard,
+    Hard,
+    Harder,
+    VeryHard,
+    CheatVision,
+    CheatMoney,
+    CheatInsane,
+}
+
+impl From<Difficulty> for sc2api::Difficulty {
+    fn from(value: Difficulty) -> Self {
+        match value {
+            Difficulty::VeryEasy => Self::VeryEasy,
+            Difficulty::Easy => Self::Easy,
+            Difficulty::Medium => Self::Medium,
+            Difficulty::MediumHard => Self::MediumHard,
+            Difficulty::Hard => Self::Hard,
+            Difficulty::Harder => Self::Harder,
+            Difficulty::VeryHard => Self::VeryHard,
+            Difficulty::CheatVision => Self::CheatVision,
+            Difficulty::CheatMoney => Self::CheatMoney,
+            Difficulty::CheatInsane => Self::CheatInsane,
+        }
+    }
+}
+
+/// Strategy used by the game's built-in AI.
+#[derive(ValueEnum, Serialize, Deserialize, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AiBuild {
+    #[default]
+    Random,
+    Rush,
+    Timing,
+    Power,
+    Macro,
+    Air,
+}
+
+impl From<AiBuild> for sc2api::AIBuild {
+    fn from(value: AiBuild) -> Self {
+        match value {
+            AiBuild::Random => Self::RandomBuild,
+            AiBuild::Rush => Self::Rush,
+            AiBuild::Timing => Self::Timing,
+            AiBuild::Power => Self::Power,
+            AiBuild::Macro => Self::Macro,
+            AiBuild::Air => Self::Air,
+        }
+    }
+}
+
+/// Setup of the players taking part in a game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameSetup {
+    pub race: Race,
+    pub name: String,
+    pub opponent: OpponentType,
+    pub opponent_race: Race,
+    pub difficulty: Difficulty,
+    pub ai_build: AiBuild,
+    /// Seed for the game's random number generator. Chosen by the game if not given.
+    pub seed: Option<u32>,
+}
+
+impl Default for GameSetup {
+    fn default() -> Self {
+        Self {
+            race: Race::Zerg,
+            name: "Tomobot".to_owned(),
+            opponent: OpponentType::Computer,
+            opponent_race: Race::Terran,
+            difficulty: Difficulty::Medium,
+            ai_build: AiBuild::Random,
+            seed: None,
+        }
+    }
+}
+
+impl GameSetup {
+    pub fn player(&self) -> sc2api::PlayerSetup {
+        sc2api::PlayerSetup {
+            type_: Some(sc2api::PlayerType::Participant.into()),
+            race: Some(common::Race::from(self.race).into()),
+            player_name: Some(self.name.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn opponent(&self) -> sc2api::PlayerSetup {
+        let mut setup = sc2api::PlayerSetup {
+            type_: Some(sc2api::PlayerType::from(self.opponent).into()),
+            race: Some(common::Race::from(self.opponent_race).into()),
+            ..Default::default()
+        };
+
+        match self.opponent {
+            OpponentType::Computer => {
+                setup.set_difficulty(self.difficulty.into());
+                setup.set_ai_build(self.ai_build.into());
+            }
+            OpponentType::Participant => setup.set_player_name(format!("{} 2", self.name)),
+            OpponentType::Observer => {
+                setup.race = None;
+                setup.set_player_name(format!("{} Observer", self.name));
+            }
+        }
+
+        setup
+    }
+}
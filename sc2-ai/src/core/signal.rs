@@ -0,0 +1,61 @@
+//! Handling of Ctrl-C.
+//!
+//! The first Ctrl-C asks every app to exit at the start of its next update, exiting with
+//! [`INTERRUPTED_EXIT_CODE`]. A second exits the process immediately, in case an app is stuck
+//! waiting on the game.
+
+use std::sync::{
+    Once,
+    atomic::{AtomicBool, Ordering},
+};
+
+use bevy::{
+    app::AppExit,
+    ecs::{
+        event::EventWriter,
+        system::{Res, ResMut},
+    },
+};
+use tracing::{error, warn};
+
+use super::{client::Client, replay::ReplaySaver};
+
+/// Exit code of an app which was interrupted, distinguishing it from an app which failed.
+pub const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Install the Ctrl-C handler. Only the first call has any effect.
+pub(super) fn install_handler() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        let result = ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::Relaxed) {
+                std::process::exit(INTERRUPTED_EXIT_CODE.into());
+            }
+            warn!("Interrupted, exiting. Interrupt again to exit immediately");
+        });
+
+        if let Err(e) = result {
+            error!("Failed to install Ctrl-C handler: {e}");
+        }
+    });
+}
+
+/// Exit the app once interrupted, saving a replay first if enabled.
+pub(super) fn exit_on_interrupt(
+    client: Option<ResMut<Client>>,
+    replays: Option<Res<ReplaySaver>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if !INTERRUPTED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if let (Some(mut client), Some(replays)) = (client, replays) {
+        replays.save(&mut client, "Interrupted");
+    }
+
+    exit.send(AppExit::from_code(INTERRUPTED_EXIT_CODE));
+}
@@ -0,0 +1,221 @@
+use bevy::ecs::system::Resource;
+
+use sc2_proto::{
+    raw::{Alliance, ObservationRaw},
+    sc2api::Observation,
+};
+
+/// Conditions which wake the app from a [`Stepping::sleep_until`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum WakeCondition {
+    /// Any of our workers is idle.
+    IdleWorkers,
+    /// The unit with this tag has no orders or no longer exists.
+    UnitIdle(u64),
+    /// Any of our units has lost life or shields, or died, since the previous step.
+    UnderAttack,
+}
+
+/// Controls how far the game is stepped between each update of the app.
+///
+/// The game is normally stepped a fixed number of loops each update. Systems may also put the app
+/// to sleep, in which case the game is stepped on without updating the app until it's woken.
+/// Events which occur while asleep are still sent once woken. Stepping has no effect in realtime,
+/// though sleeping still does.
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct Stepping {
+    count: u32,
+    wake_after: Option<u32>,
+    wake_on: Vec<WakeCondition>,
+}
+
+impl Default for Stepping {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl Stepping {
+    pub fn new(count: u32) -> Self {
+        Self {
+            count: count.max(1),
+            wake_after: None,
+            wake_on: Vec::new(),
+        }
+    }
+
+    /// Game loops to step the game by each update.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Set the game loops to step by from the next step on, or the one after if pipelined as the
+    /// next step has already started.
+    pub fn set_count(&mut self, count: u32) {
+        self.count = count.max(1);
+    }
+
+    /// Don't update the app again until `loops` game loops have passed, unless woken earlier by
+    /// a [`WakeCondition`].
+    pub fn sleep_for(&mut self, loops: u32) {
+        self.wake_after = Some(self.wake_after.map_or(loops, |after| after.min(loops)));
+    }
+
+    /// Don't update the app again until `condition` is met. Sleeping on several conditions wakes
+    /// the app on any of them.
+    pub fn sleep_until(&mut self, condition: WakeCondition) {
+        self.wake_on.push(condition);
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.wake_after.is_some() || !self.wake_on.is_empty()
+    }
+
+    pub(super) fn wake(&mut self) {
+        self.wake_after = None;
+        self.wake_on.clear();
+    }
+
+    /// Determine whether the app should be updated with `observation`, waking it if so.
+    ///
+    /// `previous` is the observation before this one, either the one the app was last updated with
+    /// or the one of the previous step while asleep. `slept_at` is the game loop the app fell
+    /// asleep on.
+    pub(super) fn wakes(
+        &mut self,
+        observation: &Observation,
+        previous: &ObservationRaw,
+        slept_at: u32,
+    ) -> bool {
+        if !self.is_asleep() {
+            return true;
+        }
+
+        let elapsed = observation.game_loop().saturating_sub(slept_at);
+        let woken = self.wake_after.is_some_and(|after| elapsed >= after)
+            || self
+                .wake_on
+                .iter()
+                .any(|condition| condition.is_met(observation, previous));
+
+        if woken {
+            self.wake();
+        }
+        woken
+    }
+}
+
+impl WakeCondition {
+    fn is_met(&self, observation: &Observation, previous: &ObservationRaw) -> bool {
+        let units = &observation.raw_data.units;
+
+        match *self {
+            WakeCondition::IdleWorkers => observation.player_common.idle_worker_count() > 0,
+            WakeCondition::UnitIdle(tag) => units
+                .iter()
+                .find(|unit| unit.tag() == tag)
+                .is_none_or(|unit| unit.orders.is_empty()),
+            WakeCondition::UnderAttack => {
+                // Units also go missing from observations while inside refineries or transports,
+                // so only units reported dead count as lost.
+                let dead = &observation.raw_data.event.dead_units;
+
+                previous
+                    .units
+                    .iter()
+                    .filter(|unit| unit.alliance() == Alliance::Self_)
+                    .any(|before| {
+                        let damaged = units
+                            .iter()
+                            .find(|unit| unit.tag() == before.tag())
+                            .is_some_and(|after| {
+                                after.health() < before.health() || after.shield() < before.shield()
+                            });
+                        damaged || dead.contains(&before.tag())
+                    })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sc2_proto::raw::{Unit, UnitOrder};
+
+    use super::*;
+
+    fn unit(tag: u64, alliance: Alliance, health: f32) -> Unit {
+        let mut unit = Unit::new();
+        unit.set_tag(tag);
+        unit.set_alliance(alliance);
+        unit.set_health(health);
+        unit
+    }
+
+    fn observation(game_loop: u32, units: Vec<Unit>) -> Observation {
+        let mut observation = Observation::new();
+        observation.set_game_loop(game_loop);
+        observation.mut_raw_data().units = units;
+        observation
+    }
+
+    #[test]
+    fn wakes_after_loops() {
+        let previous = ObservationRaw::new();
+        let mut stepping = Stepping::default();
+        assert!(stepping.wakes(&observation(1, vec![]), &previous, 0));
+
+        stepping.sleep_for(16);
+        stepping.sleep_for(8);
+        assert!(stepping.is_asleep());
+        assert!(!stepping.wakes(&observation(17, vec![]), &previous, 10));
+        assert!(stepping.wakes(&observation(18, vec![]), &previous, 10));
+        assert!(!stepping.is_asleep());
+    }
+
+    #[test]
+    fn wakes_on_idle_units() {
+        let previous = ObservationRaw::new();
+        let mut busy = unit(1, Alliance::Self_, 40.0);
+        busy.orders.push(UnitOrder::new());
+
+        let mut stepping = Stepping::default();
+        stepping.sleep_until(WakeCondition::UnitIdle(1));
+        assert!(!stepping.wakes(&observation(1, vec![busy]), &previous, 0));
+        assert!(stepping.wakes(&observation(2, vec![]), &previous, 0));
+
+        stepping.sleep_until(WakeCondition::IdleWorkers);
+        let mut idle = observation(3, vec![]);
+        assert!(!stepping.wakes(&idle, &previous, 0));
+        idle.mut_player_common().set_idle_worker_count(1);
+        assert!(stepping.wakes(&idle, &previous, 0));
+    }
+
+    #[test]
+    fn wakes_when_own_units_lost_or_damaged() {
+        let mut previous = ObservationRaw::new();
+        previous.units = vec![
+            unit(1, Alliance::Self_, 40.0),
+            unit(2, Alliance::Self_, 40.0),
+            unit(3, Alliance::Enemy, 40.0),
+        ];
+
+        let mut stepping = Stepping::default();
+        stepping.sleep_until(WakeCondition::UnderAttack);
+
+        // Missing from the observation, but not reported dead.
+        let unharmed = observation(1, vec![unit(1, Alliance::Self_, 40.0)]);
+        assert!(!stepping.wakes(&unharmed, &previous, 0));
+
+        let enemy_damaged = observation(1, vec![unit(3, Alliance::Enemy, 10.0)]);
+        assert!(!stepping.wakes(&enemy_damaged, &previous, 0));
+
+        let damaged = observation(1, vec![unit(1, Alliance::Self_, 30.0)]);
+        assert!(stepping.wakes(&damaged, &previous, 0));
+
+        stepping.sleep_until(WakeCondition::UnderAttack);
+        let mut dead = observation(1, vec![unit(1, Alliance::Self_, 40.0)]);
+        dead.mut_raw_data().mut_event().dead_units.push(2);
+        assert!(stepping.wakes(&dead, &previous, 0));
+    }
+}
@@ -0,0 +1,56 @@
+use bevy::ecs::system::Resource;
+
+/// Current time of the game, counted in game loops.
+#[derive(Resource, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct GameTime {
+    game_loop: u32,
+    elapsed: u32,
+}
+
+impl GameTime {
+    /// Game loops per second of game time on the 'faster' game speed.
+    pub const LOOPS_PER_SECOND: f32 = 22.4;
+
+    pub fn game_loop(&self) -> u32 {
+        self.game_loop
+    }
+
+    /// Number of game loops since the previous update.
+    ///
+    /// This is the step count of [`Stepping`] when the game is stepped, or more once the app wakes
+    /// from sleeping. In realtime the game doesn't wait for the app, so it varies with how long
+    /// each update takes. Systems may need to catch up on any loops beyond the first.
+    ///
+    /// [`Stepping`]: super::Stepping
+    pub fn elapsed_loops(&self) -> u32 {
+        self.elapsed
+    }
+
+    /// Game time in seconds.
+    pub fn seconds(&self) -> f32 {
+        self.game_loop as f32 / Self::LOOPS_PER_SECOND
+    }
+
+    /// Game time in minutes.
+    pub fn minutes(&self) -> f32 {
+        self.seconds() / 60.0
+    }
+
+    /// Game time in seconds since the previous update.
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed as f32 / Self::LOOPS_PER_SECOND
+    }
+
+    pub(super) fn update(&mut self, game_loop: u32) {
+        self.elapsed = game_loop.saturating_sub(self.game_loop);
+        self.game_loop = game_loop;
+    }
+}
+
+impl std::fmt::Display for GameTime {
+    /// Formats the time as shown in game, e.g. `12:05`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.seconds() as u32;
+        write!(f, "{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
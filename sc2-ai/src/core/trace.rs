@@ -0,0 +1,344 @@
+//! Human readable trace of every request sent to and response received from the game.
+//!
+//! Unlike a recording, a trace can't be played back. It's for seeing what was actually sent, e.g.
+//! when an action appears to do nothing. Each message is written on its own line, either in
+//! protobuf text format or as JSON, and the file is rotated once it grows too large.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write as _},
+    path::PathBuf,
+};
+
+use clap::ValueEnum;
+use protobuf::MessageFull;
+use serde::Serialize;
+
+use sc2_proto::sc2api::{Request, Response, request::Request as ApiRequest};
+
+/// Kinds of request, for filtering which are traced.
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum RequestKind {
+    CreateGame,
+    JoinGame,
+    RestartGame,
+    StartReplay,
+    LeaveGame,
+    QuickSave,
+    QuickLoad,
+    Quit,
+    GameInfo,
+    Observation,
+    Action,
+    ObsAction,
+    Step,
+    Data,
+    Query,
+    SaveReplay,
+    MapCommand,
+    ReplayInfo,
+    AvailableMaps,
+    SaveMap,
+    Ping,
+    Debug,
+}
+
+impl From<&ApiRequest> for RequestKind {
+    fn from(value: &ApiRequest) -> Self {
+        match value {
+            ApiRequest::CreateGame(_) => Self::CreateGame,
+            ApiRequest::JoinGame(_) => Self::JoinGame,
+            ApiRequest::RestartGame(_) => Self::RestartGame,
+            ApiRequest::StartReplay(_) => Self::StartReplay,
+            ApiRequest::LeaveGame(_) => Self::LeaveGame,
+            ApiRequest::QuickSave(_) => Self::QuickSave,
+            ApiRequest::QuickLoad(_) => Self::QuickLoad,
+            ApiRequest::Quit(_) => Self::Quit,
+            ApiRequest::GameInfo(_) => Self::GameInfo,
+            ApiRequest::Observation(_) => Self::Observation,
+            ApiRequest::Action(_) => Self::Action,
+            ApiRequest::ObsAction(_) => Self::ObsAction,
+            ApiRequest::Step(_) => Self::Step,
+            ApiRequest::Data(_) => Self::Data,
+            ApiRequest::Query(_) => Self::Query,
+            ApiRequest::SaveReplay(_) => Self::SaveReplay,
+            ApiRequest::MapCommand(_) => Self::MapCommand,
+            ApiRequest::ReplayInfo(_) => Self::ReplayInfo,
+            ApiRequest::AvailableMaps(_) => Self::AvailableMaps,
+            ApiRequest::SaveMap(_) => Self::SaveMap,
+            ApiRequest::Ping(_) => Self::Ping,
+            ApiRequest::Debug(_) => Self::Debug,
+        }
+    }
+}
+
+impl std::fmt::Display for RequestKind {
+    /// Formats the kind as given on the command line, e.g. `create-game`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("Every request kind should have a value");
+        f.write_str(value.get_name())
+    }
+}
+
+#[derive(ValueEnum, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// Protobuf text format.
+    #[default]
+    Text,
+    Json,
+}
+
+/// Where to write a trace and what to include in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceConfig {
+    pub path: PathBuf,
+    pub format: TraceFormat,
+
+    /// Only trace these kinds of request and their responses. Every kind is traced if empty.
+    pub only: Vec<RequestKind>,
+
+    /// Trace just the kind and game loop of these requests and their responses, omitting the
+    /// messages themselves. Useful for skipping large observations.
+    pub skip_bodies: Vec<RequestKind>,
+
+    /// Truncate messages longer than this many bytes.
+    pub max_message_len: Option<usize>,
+
+    /// Rotate the file once it's larger than this many bytes.
+    pub max_file_len: u64,
+
+    /// Number of rotated files to keep, as `<path>.1` being the most recent to `<path>.<n>`.
+    pub keep_files: usize,
+}
+
+impl TraceConfig {
+    pub const DEFAULT_MAX_FILE_LEN: u64 = 64 * 1024 * 1024;
+    pub const DEFAULT_KEEP_FILES: usize = 3;
+
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            format: TraceFormat::default(),
+            only: Vec::new(),
+            skip_bodies: Vec::new(),
+            max_message_len: None,
+            max_file_len: Self::DEFAULT_MAX_FILE_LEN,
+            keep_files: Self::DEFAULT_KEEP_FILES,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Tracer {
+    config: TraceConfig,
+    writer: BufWriter<File>,
+    written: u64,
+}
+
+impl Tracer {
+    pub fn create(config: TraceConfig) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(&config.path)?),
+            config,
+            written: 0,
+        })
+    }
+
+    pub fn request(&mut self, game_loop: u32, request: &Request) -> std::io::Result<()> {
+        self.trace(game_loop, request, request, "request")
+    }
+
+    pub fn response(
+        &mut self,
+        game_loop: u32,
+        request: &Request,
+        response: &Response,
+    ) -> std::io::Result<()> {
+        self.trace(game_loop, request, response, "response")
+    }
+
+    fn trace<M>(
+        &mut self,
+        game_loop: u32,
+        request: &Request,
+        message: &M,
+        direction: &str,
+    ) -> std::io::Result<()>
+    where
+        M: MessageFull,
+    {
+        let kind = request.request.as_ref().map(RequestKind::from);
+        if !self.config.only.is_empty()
+            && !kind.is_some_and(|kind| self.config.only.contains(&kind))
+        {
+            return Ok(());
+        }
+
+        let body = if kind.is_some_and(|kind| self.config.skip_bodies.contains(&kind)) {
+            None
+        } else {
+            Some(self.format(message))
+        };
+
+        let name = kind.map_or("unknown".to_owned(), |kind| kind.to_string());
+        let line = match (self.config.format, body) {
+            (TraceFormat::Text, None) => format!("{game_loop} {direction} {name}"),
+            (TraceFormat::Text, Some((body, _))) => {
+                format!("{game_loop} {direction} {name} {{ {body} }}")
+            }
+            (TraceFormat::Json, body) => {
+                let body = match body {
+                    None => String::new(),
+                    Some((body, true)) => format!(r#","message":{}"#, json_string(&body)),
+                    Some((body, false)) => format!(r#","message":{body}"#),
+                };
+                format!(
+                    r#"{{"game_loop":{game_loop},"direction":"{direction}","kind":"{name}"{body}}}"#
+                )
+            }
+        };
+
+        self.write_line(&line)
+    }
+
+    /// Format a message, returning whether it was truncated.
+    fn format<M>(&self, message: &M) -> (String, bool)
+    where
+        M: MessageFull,
+    {
+        let mut body = match self.config.format {
+            TraceFormat::Text => protobuf::text_format::print_to_string(message),
+            TraceFormat::Json => protobuf_json_mapping::print_to_string(message)
+                .unwrap_or_else(|e| json_string(&format!("Failed to format message: {e}"))),
+        };
+
+        match self.config.max_message_len {
+            Some(max) if body.len() > max => {
+                let end = (0..=max)
+                    .rev()
+                    .find(|&i| body.is_char_boundary(i))
+                    .unwrap_or(0);
+                body.truncate(end);
+                body.push_str("...");
+                (body, true)
+            }
+            _ => (body, false),
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 > self.config.max_file_len {
+            self.rotate()?;
+        }
+
+        writeln!(self.writer, "{line}")?;
+        self.writer.flush()?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Move the current file to `<path>.1`, shifting older files along and removing the oldest.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+
+        let rotated = |i: usize| {
+            let mut path = self.config.path.clone().into_os_string();
+            path.push(format!(".{i}"));
+            PathBuf::from(path)
+        };
+
+        if self.config.keep_files > 0 {
+            for i in (1..self.config.keep_files).rev() {
+                if rotated(i).exists() {
+                    std::fs::rename(rotated(i), rotated(i + 1))?;
+                }
+            }
+            std::fs::rename(&self.config.path, rotated(1))?;
+        }
+
+        self.writer = BufWriter::new(File::create(&self.config.path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn json_string(value: &str) -> String {
+    serde_json::to_string(value).expect("Strings should always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sc2-ai-{}-{name}", std::process::id()))
+    }
+
+    fn read_lines(path: &std::path::Path) -> Vec<String> {
+        let lines = std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        let _ = std::fs::remove_file(path);
+        lines
+    }
+
+    fn ping() -> Request {
+        let mut request = Request::new();
+        request.mut_ping();
+        request
+    }
+
+    #[test]
+    fn filters_requests() {
+        let path = temp_path("trace-filters");
+        let mut tracer = Tracer::create(TraceConfig {
+            only: vec![RequestKind::Ping, RequestKind::Step],
+            skip_bodies: vec![RequestKind::Step],
+            ..TraceConfig::new(path.clone())
+        })
+        .unwrap();
+
+        let mut step = Request::new();
+        step.mut_step().set_count(1);
+        let mut observation = Request::new();
+        observation.mut_observation();
+
+        tracer.request(0, &ping()).unwrap();
+        tracer.response(0, &ping(), &Response::new()).unwrap();
+        tracer.request(0, &step).unwrap();
+        tracer.request(1, &observation).unwrap();
+
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0 request ping {"));
+        assert!(lines[1].starts_with("0 response ping {"));
+        assert_eq!(lines[2], "0 request step");
+    }
+
+    #[test]
+    fn rotates_files() {
+        let path = temp_path("trace-rotation");
+        let rotated = |i: usize| PathBuf::from(format!("{}.{i}", path.display()));
+        let mut tracer = Tracer::create(TraceConfig {
+            skip_bodies: vec![RequestKind::Ping],
+            max_file_len: 20,
+            keep_files: 2,
+            ..TraceConfig::new(path.clone())
+        })
+        .unwrap();
+
+        for game_loop in 1..=4 {
+            tracer.request(game_loop, &ping()).unwrap();
+        }
+
+        assert!(!rotated(3).exists());
+        assert_eq!(read_lines(&rotated(2)), ["2 request ping"]);
+        assert_eq!(read_lines(&rotated(1)), ["3 request ping"]);
+        assert_eq!(read_lines(&path), ["4 request ping"]);
+    }
+}
@@ -0,0 +1,137 @@
+//! Games between two instances of the bot within a single process.
+
+use std::sync::{Arc, Barrier, Mutex};
+
+use tracing::info;
+
+use super::{
+    CorePlugin, GameSetup, OpponentType, PlayerId, Session, client::GamePorts, error::CoreError,
+    process, restart::Role,
+};
+
+impl CorePlugin {
+    /// Launch two game instances and start a game between them.
+    ///
+    /// The game is created by the first instance and both join with a shared set of ports. Returns
+    /// a plugin for each player, each of which must be added to a separate app. Only the first
+    /// player's requests are recorded or traced, and only its metrics exported.
+    ///
+    /// With an [`OpponentType::Observer`] opponent, the second instance observes the first player
+    /// rather than playing against it, running the same systems without sending any actions.
+    pub fn start_versus(self) -> Result<[CorePlugin; 2], CoreError> {
+        let observer = self.setup.opponent == OpponentType::Observer;
+        let setup = GameSetup {
+            opponent: if observer {
+                OpponentType::Observer
+            } else {
+                OpponentType::Participant
+            },
+            ..self.setup.clone()
+        };
+        let (host, guest) = (setup.player(), setup.opponent());
+
+        info!("Launching clients");
+        let (host_process, mut host_client) = process::launch_client(self.connect_timeout)?;
+        let (guest_process, mut guest_client) = process::launch_client(self.connect_timeout)?;
+
+        if let Some(path) = &self.record {
+            info!("Recording game to {}", path.display());
+            host_client.record_to(path)?;
+        }
+        if let Some(config) = &self.trace {
+            info!("Tracing requests to {}", config.path.display());
+            host_client.trace_to(config.clone())?;
+        }
+
+        info!("Starting game");
+        let map = format!("{}.SC2Map", self.map);
+        host_client.start_game(
+            map.clone(),
+            host.clone(),
+            guest.clone(),
+            self.realtime,
+            setup.seed,
+        )?;
+
+        let start_port =
+            process::get_unused_port_range(GamePorts::range_len(1)).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::AddrNotAvailable,
+                    "No free ports for multiplayer game",
+                )
+            })?;
+        let ports = GamePorts::from_start_port(start_port, 1);
+
+        // Joining blocks until every player has joined so both must join concurrently.
+        info!("Joining game");
+        let (host_id, guest_id) = std::thread::scope(|scope| {
+            let guest_join =
+                scope.spawn(|| guest_client.join_game(guest.clone(), Some(&ports), self.interface));
+            let host_id = host_client.join_game(host, Some(&ports), self.interface);
+            let guest_id = guest_join
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e));
+
+            (host_id, guest_id)
+        });
+
+        let created = Arc::new(Barrier::new(2));
+
+        let guest_plugin = CorePlugin {
+            mode: self.mode.clone(),
+            map: self.map.clone(),
+            realtime: self.realtime,
+            pipelined: self.pipelined,
+            record: None,
+            trace: None,
+            metrics: None,
+            replay_dir: self.replay_dir.clone(),
+            interface: self.interface,
+            connect_timeout: self.connect_timeout,
+            // An observer sees the game from the first player's side.
+            setup: if observer {
+                setup.clone()
+            } else {
+                GameSetup {
+                    race: setup.opponent_race,
+                    name: guest.player_name().to_owned(),
+                    opponent_race: setup.race,
+                    ..setup.clone()
+                }
+            },
+            games: self.games,
+            step_count: self.step_count,
+            session: Mutex::new(Some(Session {
+                process: Some(guest_process),
+                client: guest_client,
+                player: PlayerId(guest_id?),
+                role: Role::Guest {
+                    ports: ports.clone(),
+                    created: created.clone(),
+                },
+                observer,
+            })),
+        };
+
+        let host_plugin = CorePlugin {
+            session: Mutex::new(Some(Session {
+                process: Some(host_process),
+                client: host_client,
+                player: PlayerId(host_id?),
+                role: Role::Host {
+                    map,
+                    opponent: guest,
+                    realtime: self.realtime,
+                    seed: setup.seed,
+                    ports,
+                    created,
+                },
+                observer: false,
+            })),
+            setup,
+            ..self
+        };
+
+        Ok([host_plugin, guest_plugin])
+    }
+}
@@ -0,0 +1,41 @@
+use bevy::ecs::{
+    entity::Entity,
+    event::{Event, EventWriter},
+    system::Res,
+};
+use num_traits::FromPrimitive as _;
+
+use sc2_proto::{AbilityId, error::ActionResult};
+
+use crate::{
+    core::ApiActionErrors,
+    game::entity::{EntityIdMap, GameId},
+};
+
+/// An action sent on the previous step failed.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ActionErrorEvent {
+    /// Unit the action was issued to, if any and if it's known of.
+    pub unit: Option<Entity>,
+    pub ability: Option<AbilityId>,
+    pub result: ActionResult,
+}
+
+/// Bevy systems.
+impl ActionErrorEvent {
+    pub fn send(
+        errors: Res<ApiActionErrors>,
+        map: Res<EntityIdMap>,
+        mut events: EventWriter<ActionErrorEvent>,
+    ) {
+        events.send_batch(errors.iter().map(|error| {
+            ActionErrorEvent {
+                unit: error
+                    .unit_tag
+                    .and_then(|tag| map.get(&GameId::from(tag)).copied()),
+                ability: error.ability_id.and_then(AbilityId::from_u64),
+                result: error.result(),
+            }
+        }));
+    }
+}
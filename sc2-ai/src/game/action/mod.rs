@@ -3,9 +3,11 @@ use bevy::ecs::entity::Entity;
 use super::{entity::BuildingEntity, geometry::Vec2};
 
 mod build;
+mod error;
 mod r#move;
 
 pub use build::BuildCommandsExt;
+pub use error::ActionErrorEvent;
 pub use r#move::{MoveCommandsExt, MoveEvent};
 
 pub trait ActionCommandsExt: MoveCommandsExt + BuildCommandsExt {
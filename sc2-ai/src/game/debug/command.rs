@@ -0,0 +1,241 @@
+use bevy::ecs::{
+    entity::Entity,
+    world::{Command, World},
+};
+use num_traits::ToPrimitive;
+
+use sc2_proto::{
+    debug::{
+        DebugCreateUnit, DebugEndGame, DebugGameState, DebugKillUnit, DebugSetUnitValue,
+        DebugTestProcess, debug_end_game, debug_set_unit_value, debug_test_process,
+    },
+    unit::TypeId,
+};
+
+use crate::{
+    core::DebugCommands,
+    game::{entity::game_id, geometry::Vec2},
+};
+
+/// Unit values which can be set through [`DebugCommandsExt::set_unit_value`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum UnitValue {
+    Energy,
+    Life,
+    Shields,
+}
+
+impl From<UnitValue> for debug_set_unit_value::UnitValue {
+    fn from(value: UnitValue) -> Self {
+        match value {
+            UnitValue::Energy => Self::Energy,
+            UnitValue::Life => Self::Life,
+            UnitValue::Shields => Self::Shields,
+        }
+    }
+}
+
+/// Game state cheats which can be toggled through [`DebugCommandsExt::toggle_game_state`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum GameStateToggle {
+    /// Reveal the whole map.
+    ShowMap,
+    /// Allow control of the enemy's units.
+    ControlEnemy,
+    /// Disable the supply requirement.
+    Food,
+    /// Make everything free to build.
+    FreeBuild,
+    /// Give every player 5000 minerals and vespene.
+    AllResources,
+    /// Make our units invulnerable.
+    God,
+    /// Give every player 5000 minerals.
+    Minerals,
+    /// Give every player 5000 vespene.
+    Gas,
+    /// Disable ability cooldowns.
+    Cooldown,
+    /// Ignore tech requirements.
+    TechTree,
+    /// Research every upgrade.
+    Upgrade,
+    /// Make everything build much faster.
+    FastBuild,
+}
+
+impl From<GameStateToggle> for DebugGameState {
+    fn from(value: GameStateToggle) -> Self {
+        match value {
+            GameStateToggle::ShowMap => Self::show_map,
+            GameStateToggle::ControlEnemy => Self::control_enemy,
+            GameStateToggle::Food => Self::food,
+            GameStateToggle::FreeBuild => Self::free,
+            GameStateToggle::AllResources => Self::all_resources,
+            GameStateToggle::God => Self::god,
+            GameStateToggle::Minerals => Self::minerals,
+            GameStateToggle::Gas => Self::gas,
+            GameStateToggle::Cooldown => Self::cooldown,
+            GameStateToggle::TechTree => Self::tech_tree,
+            GameStateToggle::Upgrade => Self::upgrade,
+            GameStateToggle::FastBuild => Self::fast_build,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum EndResult {
+    Surrender,
+    Victory,
+}
+
+impl From<EndResult> for debug_end_game::EndResult {
+    fn from(value: EndResult) -> Self {
+        match value {
+            EndResult::Surrender => Self::Surrender,
+            EndResult::Victory => Self::DeclareVictory,
+        }
+    }
+}
+
+/// Faults which can be injected into the game process, for testing how the bot copes.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum TestProcess {
+    /// Hang the game for a number of milliseconds.
+    Hang(u32),
+    Crash,
+    Exit,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum DebugAction {
+    CreateUnit {
+        unit_type: TypeId,
+        owner: u32,
+        position: Vec2,
+        quantity: u32,
+    },
+    KillUnits(Box<[Entity]>),
+    SetUnitValue {
+        unit: Entity,
+        value: UnitValue,
+        amount: f32,
+    },
+    ToggleGameState(GameStateToggle),
+    EndGame(EndResult),
+    TestProcess(TestProcess),
+}
+
+impl Command for DebugAction {
+    fn apply(self, world: &mut World) {
+        let mut command = sc2_proto::debug::DebugCommand::new();
+
+        match self {
+            DebugAction::CreateUnit {
+                unit_type,
+                owner,
+                position,
+                quantity,
+            } => {
+                command.set_create_unit(DebugCreateUnit {
+                    unit_type: unit_type.to_u32(),
+                    owner: Some(owner as i32),
+                    pos: protobuf::MessageField::some(position.into()),
+                    quantity: Some(quantity),
+                    ..Default::default()
+                });
+            }
+            DebugAction::KillUnits(units) => {
+                command.set_kill_unit(DebugKillUnit {
+                    tag: units.iter().map(|&unit| game_id(world, unit)).collect(),
+                    ..Default::default()
+                });
+            }
+            DebugAction::SetUnitValue {
+                unit,
+                value,
+                amount,
+            } => {
+                let mut set_value = DebugSetUnitValue::new();
+                set_value.set_unit_value(value.into());
+                set_value.set_value(amount);
+                set_value.set_unit_tag(game_id(world, unit));
+                command.set_unit_value(set_value);
+            }
+            DebugAction::ToggleGameState(state) => command.set_game_state(state.into()),
+            DebugAction::EndGame(result) => {
+                let mut end_game = DebugEndGame::new();
+                end_game.set_end_result(result.into());
+                command.set_end_game(end_game);
+            }
+            DebugAction::TestProcess(test) => {
+                let mut process = DebugTestProcess::new();
+                match test {
+                    TestProcess::Hang(delay_ms) => {
+                        process.set_test(debug_test_process::Test::hang);
+                        process.set_delay_ms(delay_ms as i32);
+                    }
+                    TestProcess::Crash => process.set_test(debug_test_process::Test::crash),
+                    TestProcess::Exit => process.set_test(debug_test_process::Test::exit),
+                }
+                command.set_test_process(process);
+            }
+        }
+
+        world.resource_mut::<DebugCommands>().push(command);
+    }
+}
+
+#[allow(unused)]
+pub trait DebugCommandsExt {
+    /// Spawn units for a player. Player IDs start at 1.
+    fn create_units(&mut self, unit_type: TypeId, owner: u32, position: Vec2, quantity: u32);
+    fn kill_units(&mut self, units: &[Entity]);
+    fn set_unit_value(&mut self, unit: Entity, value: UnitValue, amount: f32);
+    fn toggle_game_state(&mut self, state: GameStateToggle);
+    fn end_game(&mut self, result: EndResult);
+    fn test_process(&mut self, test: TestProcess);
+
+    fn create_unit(&mut self, unit_type: TypeId, owner: u32, position: Vec2) {
+        self.create_units(unit_type, owner, position, 1);
+    }
+
+    fn kill_unit(&mut self, unit: Entity) {
+        self.kill_units(&[unit]);
+    }
+}
+
+impl DebugCommandsExt for bevy::ecs::system::Commands<'_, '_> {
+    fn create_units(&mut self, unit_type: TypeId, owner: u32, position: Vec2, quantity: u32) {
+        self.queue(DebugAction::CreateUnit {
+            unit_type,
+            owner,
+            position,
+            quantity,
+        });
+    }
+
+    fn kill_units(&mut self, units: &[Entity]) {
+        self.queue(DebugAction::KillUnits(Box::from(units)));
+    }
+
+    fn set_unit_value(&mut self, unit: Entity, value: UnitValue, amount: f32) {
+        self.queue(DebugAction::SetUnitValue {
+            unit,
+            value,
+            amount,
+        });
+    }
+
+    fn toggle_game_state(&mut self, state: GameStateToggle) {
+        self.queue(DebugAction::ToggleGameState(state));
+    }
+
+    fn end_game(&mut self, result: EndResult) {
+        self.queue(DebugAction::EndGame(result));
+    }
+
+    fn test_process(&mut self, test: TestProcess) {
+        self.queue(DebugAction::TestProcess(test));
+    }
+}
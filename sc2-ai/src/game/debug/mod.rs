@@ -1,4 +1,5 @@
 mod color;
+mod command;
 mod cuboid;
 mod draw;
 mod line;
@@ -6,4 +7,5 @@ mod sphere;
 mod text;
 
 pub use color::Color;
+pub use command::{DebugCommandsExt, EndResult, GameStateToggle, TestProcess, UnitValue};
 pub use draw::DrawCommandsExt;
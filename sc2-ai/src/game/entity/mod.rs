@@ -10,6 +10,7 @@ use bevy::{
         event::Event,
         query::With,
         system::{Commands, Query, Resource},
+        world::World,
     },
     utils::HashMap,
 };
@@ -23,12 +24,37 @@ use super::{
 #[derive(Component, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub struct GameId(pub(super) u64);
 
+impl From<u64> for GameId {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
 impl From<GameId> for u64 {
     fn from(value: GameId) -> Self {
         value.0
     }
 }
 
+/// Tag of a game entity, for referring to it in requests to the game.
+///
+/// # Panics
+///
+/// If `entity` doesn't exist or has no [`GameId`].
+pub(crate) fn game_id(world: &World, entity: Entity) -> u64 {
+    let id = world
+        .entity(entity)
+        .get::<GameId>()
+        .expect("Every game entity should have a GameId component");
+
+    u64::from(*id)
+}
+
+/// Marks an enemy entity which may only be known of because fog of war is disabled. A bot playing
+/// fairly shouldn't act on these.
+#[derive(Component, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Revealed;
+
 /// Maps from an entity's [`GameId`] to it's bevy [`Entity`] ID.
 #[derive(Resource, Default, Clone, Debug, PartialEq, Eq)]
 pub struct EntityIdMap(HashMap<GameId, Entity>);
@@ -0,0 +1,172 @@
+//! Feature layers of the screen and minimap, decoded into grids indexed by `(y, x)`.
+//!
+//! Layers are only observed if enabled through [`InterfaceConfig::feature_layers`]. Layers the game
+//! didn't render, or which can't be decoded, are left empty.
+//!
+//! [`InterfaceConfig::feature_layers`]: crate::core::InterfaceConfig::feature_layers
+
+use bevy::ecs::system::{Res, ResMut, Resource};
+use ndarray::Array2;
+use protobuf::MessageField;
+
+use sc2_proto::{
+    common::ImageData,
+    spatial::{FeatureLayers, FeatureLayersMinimap},
+};
+use tracing::warn;
+
+use super::map::unpack_image;
+use crate::core::{ApiFeatureLayers, InterfaceConfig};
+
+/// Feature layers of the area of the map shown on screen.
+#[derive(Resource, Default, Clone, Debug, PartialEq)]
+pub struct ScreenFeatures {
+    /// Terrain height, scaled to the range 0-255.
+    pub height_map: Array2<u8>,
+    /// 0 if hidden, 1 if fogged and 2 if visible.
+    pub visibility_map: Array2<u8>,
+    pub creep: Array2<bool>,
+    pub power: Array2<bool>,
+    pub player_id: Array2<u8>,
+    /// 0 if empty, then 1 for self, 2 for allies, 3 for neutral and 4 for enemies.
+    pub player_relative: Array2<u8>,
+    pub unit_type: Array2<u32>,
+    pub selected: Array2<bool>,
+    pub unit_hit_points: Array2<u32>,
+    pub unit_hit_points_ratio: Array2<u8>,
+    pub unit_energy: Array2<u32>,
+    pub unit_energy_ratio: Array2<u8>,
+    pub unit_shields: Array2<u32>,
+    pub unit_shields_ratio: Array2<u8>,
+    pub unit_density: Array2<u8>,
+    pub unit_density_aa: Array2<u8>,
+    pub effects: Array2<u8>,
+    pub hallucinations: Array2<bool>,
+    pub cloaked: Array2<bool>,
+    pub blip: Array2<bool>,
+    pub buffs: Array2<u32>,
+    pub buff_duration: Array2<u8>,
+    pub active: Array2<bool>,
+    pub build_progress: Array2<u8>,
+    pub buildable: Array2<bool>,
+    pub pathable: Array2<bool>,
+    pub placeholder: Array2<bool>,
+}
+
+/// Feature layers of the minimap, covering the whole map.
+#[derive(Resource, Default, Clone, Debug, PartialEq)]
+pub struct MinimapFeatures {
+    /// Terrain height, scaled to the range 0-255.
+    pub height_map: Array2<u8>,
+    /// 0 if hidden, 1 if fogged and 2 if visible.
+    pub visibility_map: Array2<u8>,
+    pub creep: Array2<bool>,
+    /// Area of the map shown on screen.
+    pub camera: Array2<bool>,
+    pub player_id: Array2<u8>,
+    /// 0 if empty, then 1 for self, 2 for allies, 3 for neutral and 4 for enemies.
+    pub player_relative: Array2<u8>,
+    pub selected: Array2<bool>,
+    pub alerts: Array2<bool>,
+    pub buildable: Array2<bool>,
+    pub pathable: Array2<bool>,
+    pub unit_type: Array2<u32>,
+}
+
+fn layer<T>(image: &MessageField<ImageData>, convert: impl Fn(u32) -> T) -> Array2<T>
+where
+    T: Default,
+{
+    image
+        .as_ref()
+        .and_then(|image| {
+            unpack_image(image)
+                .inspect_err(|e| warn!("Skipping feature layer: {e}"))
+                .ok()
+        })
+        .map(|image| image.mapv(convert))
+        .unwrap_or_default()
+}
+
+fn flag(pixel: u32) -> bool {
+    pixel != 0
+}
+
+fn byte(pixel: u32) -> u8 {
+    pixel as u8
+}
+
+fn raw(pixel: u32) -> u32 {
+    pixel
+}
+
+impl From<&FeatureLayers> for ScreenFeatures {
+    fn from(value: &FeatureLayers) -> Self {
+        Self {
+            height_map: layer(&value.height_map, byte),
+            visibility_map: layer(&value.visibility_map, byte),
+            creep: layer(&value.creep, flag),
+            power: layer(&value.power, flag),
+            player_id: layer(&value.player_id, byte),
+            player_relative: layer(&value.player_relative, byte),
+            unit_type: layer(&value.unit_type, raw),
+            selected: layer(&value.selected, flag),
+            unit_hit_points: layer(&value.unit_hit_points, raw),
+            unit_hit_points_ratio: layer(&value.unit_hit_points_ratio, byte),
+            unit_energy: layer(&value.unit_energy, raw),
+            unit_energy_ratio: layer(&value.unit_energy_ratio, byte),
+            unit_shields: layer(&value.unit_shields, raw),
+            unit_shields_ratio: layer(&value.unit_shields_ratio, byte),
+            unit_density: layer(&value.unit_density, byte),
+            unit_density_aa: layer(&value.unit_density_aa, byte),
+            effects: layer(&value.effects, byte),
+            hallucinations: layer(&value.hallucinations, flag),
+            cloaked: layer(&value.cloaked, flag),
+            blip: layer(&value.blip, flag),
+            buffs: layer(&value.buffs, raw),
+            buff_duration: layer(&value.buff_duration, byte),
+            active: layer(&value.active, flag),
+            build_progress: layer(&value.build_progress, byte),
+            buildable: layer(&value.buildable, flag),
+            pathable: layer(&value.pathable, flag),
+            placeholder: layer(&value.placeholder, flag),
+        }
+    }
+}
+
+impl From<&FeatureLayersMinimap> for MinimapFeatures {
+    fn from(value: &FeatureLayersMinimap) -> Self {
+        Self {
+            height_map: layer(&value.height_map, byte),
+            visibility_map: layer(&value.visibility_map, byte),
+            creep: layer(&value.creep, flag),
+            camera: layer(&value.camera, flag),
+            player_id: layer(&value.player_id, byte),
+            player_relative: layer(&value.player_relative, byte),
+            selected: layer(&value.selected, flag),
+            alerts: layer(&value.alerts, flag),
+            buildable: layer(&value.buildable, flag),
+            pathable: layer(&value.pathable, flag),
+            unit_type: layer(&value.unit_type, raw),
+        }
+    }
+}
+
+/// Bevy systems.
+impl ScreenFeatures {
+    pub fn update(layers: Res<ApiFeatureLayers>, mut features: ResMut<ScreenFeatures>) {
+        *features = ScreenFeatures::from(layers.renders.get_or_default());
+    }
+}
+
+/// Bevy systems.
+impl MinimapFeatures {
+    pub fn update(layers: Res<ApiFeatureLayers>, mut features: ResMut<MinimapFeatures>) {
+        *features = MinimapFeatures::from(layers.minimap_renders.get_or_default());
+    }
+}
+
+/// Run condition for systems which decode feature layers.
+pub fn feature_layers_enabled(interface: Res<InterfaceConfig>) -> bool {
+    interface.feature_layers.is_some()
+}
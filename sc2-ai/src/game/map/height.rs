@@ -1,24 +1,21 @@
 use bevy::ecs::system::{Commands, Res, ResMut, Resource};
 use duplicate::duplicate_item;
+use tracing::warn;
 
+use super::unpack_image;
 use crate::{core::ApiMapInfo, game::geometry::Vec2};
 
 #[derive(Resource, Default, Clone, Debug, PartialEq)]
 pub struct HeightMap(ndarray::Array2<f32>);
 
 impl From<sc2_proto::common::ImageData> for HeightMap {
-    fn from(mut value: sc2_proto::common::ImageData) -> Self {
+    fn from(value: sc2_proto::common::ImageData) -> Self {
         debug_assert!(value.bits_per_pixel() == 8);
 
-        let inner = ndarray::Array2::from_shape_vec(
-            (value.size.y() as usize, value.size.x() as usize),
-            value
-                .take_data()
-                .into_iter()
-                .map(|height| (((height as f32) * 32.0) / 255.0) - 16.0)
-                .collect(),
-        )
-        .expect("Image data incompatible with placement grid");
+        let inner = unpack_image(&value)
+            .inspect_err(|e| warn!("Ignoring height map: {e}"))
+            .unwrap_or_default()
+            .mapv(|height| (((height as f32) * 32.0) / 255.0) - 16.0);
 
         Self(inner)
     }
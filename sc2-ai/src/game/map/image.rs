@@ -0,0 +1,107 @@
+use sc2_proto::common::ImageData;
+use thiserror::Error;
+
+/// Reasons an image can't be unpacked.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ImageError {
+    #[error("Unsupported image with {0} bits per pixel")]
+    BitsPerPixel(i32),
+
+    #[error("Image of {width}x{height} has data for only {pixels} pixels")]
+    Size {
+        width: i32,
+        height: i32,
+        pixels: usize,
+    },
+}
+
+/// Unpack the pixels of an image into a grid indexed by `(y, x)`.
+///
+/// Images with 1 bit per pixel are packed with the first pixel in the most significant bit of each
+/// byte. Wider pixels are little endian.
+pub(crate) fn unpack_image(image: &ImageData) -> Result<ndarray::Array2<u32>, ImageError> {
+    let (width, height) = (image.size.x(), image.size.y());
+    let data = image.data();
+
+    let mut pixels: Vec<u32> = match image.bits_per_pixel() {
+        1 => data
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| u32::from((byte >> i) & 1)))
+            .collect(),
+        8 => data.iter().map(|&pixel| u32::from(pixel)).collect(),
+        16 => data
+            .chunks_exact(2)
+            .map(|pixel| u32::from(u16::from_le_bytes([pixel[0], pixel[1]])))
+            .collect(),
+        32 => data
+            .chunks_exact(4)
+            .map(|pixel| u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+            .collect(),
+        bits => return Err(ImageError::BitsPerPixel(bits)),
+    };
+
+    let size_error = ImageError::Size {
+        width,
+        height,
+        pixels: pixels.len(),
+    };
+    let shape = match (usize::try_from(height), usize::try_from(width)) {
+        (Ok(height), Ok(width)) => (height, width),
+        _ => return Err(size_error),
+    };
+
+    // The last byte of a 1 bit image may be padded.
+    match shape.0.checked_mul(shape.1) {
+        Some(len) if len <= pixels.len() => pixels.truncate(len),
+        _ => return Err(size_error),
+    }
+
+    ndarray::Array2::from_shape_vec(shape, pixels).map_err(|_| size_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf::MessageField;
+    use sc2_proto::common::Size2DI;
+
+    use super::*;
+
+    fn image(bits_per_pixel: i32, width: i32, height: i32, data: Vec<u8>) -> ImageData {
+        ImageData {
+            bits_per_pixel: Some(bits_per_pixel),
+            size: MessageField::some(Size2DI {
+                x: Some(width),
+                y: Some(height),
+                ..Default::default()
+            }),
+            data: Some(data.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unpacks_pixels() {
+        let bits = unpack_image(&image(1, 3, 3, vec![0b1000_0001, 0b1000_0000])).unwrap();
+        assert_eq!(bits, ndarray::array![[1, 0, 0], [0, 0, 0], [0, 1, 1]]);
+
+        let wide = unpack_image(&image(16, 2, 1, vec![0x01, 0x02, 0xFF, 0x00])).unwrap();
+        assert_eq!(wide, ndarray::array![[0x0201, 0x00FF]]);
+    }
+
+    #[test]
+    fn rejects_malformed_images() {
+        assert_eq!(
+            unpack_image(&image(4, 2, 2, vec![0; 2])),
+            Err(ImageError::BitsPerPixel(4))
+        );
+        assert_eq!(
+            unpack_image(&image(8, 4, 4, vec![0; 8])),
+            Err(ImageError::Size {
+                width: 4,
+                height: 4,
+                pixels: 8
+            })
+        );
+        assert!(unpack_image(&image(8, -1, 4, vec![0; 8])).is_err());
+    }
+}
@@ -1,5 +1,8 @@
 mod height;
+mod image;
 mod placement;
 
 pub use height::HeightMap;
 pub use placement::PlacementGrid;
+
+pub(crate) use image::unpack_image;
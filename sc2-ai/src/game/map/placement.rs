@@ -4,7 +4,9 @@ use bevy::ecs::{
     system::{Commands, Query, Res, ResMut, Resource},
 };
 use ndarray::s;
+use tracing::warn;
 
+use super::unpack_image;
 use crate::{
     core::ApiMapInfo,
     game::{
@@ -28,26 +30,16 @@ impl From<sc2_proto::common::ImageData> for PlacementGrid {
     fn from(value: sc2_proto::common::ImageData) -> Self {
         debug_assert!(value.bits_per_pixel() == 1);
 
-        let inner = ndarray::Array2::from_shape_vec(
-            (value.size.y() as usize, value.size.x() as usize),
-            value
-                .data()
-                .iter()
-                .flat_map(|byte| {
-                    (0..8)
-                        .rev()
-                        .map(|i| {
-                            if ((byte >> i) & 1) == 0 {
-                                GridStatus::Invalid
-                            } else {
-                                GridStatus::Empty
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .collect(),
-        )
-        .expect("Image data incompatible with placement grid");
+        let inner = unpack_image(&value)
+            .inspect_err(|e| warn!("Ignoring placement grid: {e}"))
+            .unwrap_or_default()
+            .mapv(|bit| {
+                if bit == 0 {
+                    GridStatus::Invalid
+                } else {
+                    GridStatus::Empty
+                }
+            });
 
         Self(inner)
     }
@@ -1,15 +1,17 @@
 //! Deals with transforming data between the SC2 API and types moe suitable for use in ECS systems.
 
-use action::MoveEvent;
+use action::{ActionErrorEvent, MoveEvent};
 use bevy::{
     app::{App, MainScheduleOrder, Plugin, Startup, Update},
     ecs::{
+        entity::Entity,
         schedule::{IntoSystemConfigs, ScheduleLabel},
-        system::{Commands, Res, ResMut},
+        system::{Commands, Local, Res, ResMut},
     },
+    utils::HashSet,
 };
 use entity::{
-    EntityBundle, EntityFound, EntityIdMap, GameId,
+    EntityBundle, EntityFound, EntityIdMap, GameId, Revealed,
     building::{HatcheryBundle, LarvaBundle},
     map::{
         DestructibleRockBundle, MineralPatch, MineralPatchBundle, RichMinerals, VespeneGeyser,
@@ -17,19 +19,23 @@ use entity::{
     },
     unit::{OverlordBundle, WorkerBundle},
 };
+use feature::{MinimapFeatures, ScreenFeatures, feature_layers_enabled};
 use geometry::Vec3;
 use map::{HeightMap, PlacementGrid};
 use num_traits::FromPrimitive;
+use sc2_proto::raw::{Alliance, Unit};
 use tracing::warn;
 
-use crate::core::ApiObservation;
+use crate::core::{ApiObservation, InterfaceConfig, TimeScheduleExt as _, game_joined};
 
 pub mod action;
 pub mod debug;
 pub mod entity;
+pub mod feature;
 pub mod geometry;
 pub mod map;
 mod player;
+pub mod query;
 
 #[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct GamePlugin;
@@ -49,9 +55,16 @@ impl Plugin for GamePlugin {
         schedule_order.insert_startup_before(Startup, DataInit);
         schedule_order.insert_before(Update, DataUpdate);
 
+        app.time_startup_schedule(DataInit, "data-init")
+            .time_schedule(DataUpdate, "data-update")
+            .time_schedule(Update, "update");
+
         app.init_resource::<EntityIdMap>();
+        app.init_resource::<ScreenFeatures>();
+        app.init_resource::<MinimapFeatures>();
 
         app.add_event::<MoveEvent>();
+        app.add_event::<ActionErrorEvent>();
         app.add_event::<EntityFound<MineralPatch>>();
         app.add_event::<EntityFound<VespeneGeyser>>();
 
@@ -64,141 +77,206 @@ impl Plugin for GamePlugin {
                 PlacementGrid::entity_found_handler::<MineralPatch>,
                 PlacementGrid::entity_found_handler::<VespeneGeyser>,
             )
-                .chain(),
+                .chain()
+                .run_if(game_joined),
+        );
+        app.add_systems(
+            DataUpdate,
+            (update_entities, ActionErrorEvent::send)
+                .chain()
+                .run_if(game_joined),
+        );
+        app.add_systems(
+            DataUpdate,
+            (ScreenFeatures::update, MinimapFeatures::update)
+                .run_if(game_joined)
+                .run_if(feature_layers_enabled),
         );
-        app.add_systems(DataUpdate, update_entities);
     }
 }
 
 /// Create entities by evaluating the [`Observation`] resource.
+///
+/// This runs again at the start of every restarted game, so any mappings to the entities of the
+/// previous game are removed first.
 fn create_entities(
     mut commands: Commands,
     observation: Res<ApiObservation>,
+    interface: Res<InterfaceConfig>,
     mut map: ResMut<EntityIdMap>,
 ) {
+    map.clear();
+
     for unit in &observation.units {
-        use sc2_proto::unit::TypeId;
-        let unit_type = TypeId::from_u32(unit.unit_type()).unwrap();
-
-        let entity = match unit_type {
-            TypeId::MineralField
-            | TypeId::MineralField450
-            | TypeId::MineralField750
-            | TypeId::LabMineralField
-            | TypeId::LabMineralField750
-            | TypeId::PurifierMineralField
-            | TypeId::PurifierMineralField750
-            | TypeId::BattleStationMineralField
-            | TypeId::BattleStationMineralField750 => {
-                let entity = MineralPatchBundle {
-                    unit: EntityBundle::from(unit.clone()),
-                    ..Default::default()
-                };
-
-                let entity = commands.spawn(entity).id();
-                commands.send_event(EntityFound::<MineralPatch>::from(entity));
-                entity
-            }
+        let Some(entity) = spawn_entity(&mut commands, unit) else {
+            warn!("Unhandled unit: {unit:?}");
+            continue;
+        };
 
-            TypeId::PurifierRichMineralField
-            | TypeId::PurifierRichMineralField750
-            | TypeId::RichMineralField
-            | TypeId::RichMineralField750 => {
-                let entity = MineralPatchBundle {
-                    unit: EntityBundle::from(unit.clone()),
-                    ..Default::default()
-                };
-
-                let entity = commands.spawn((entity, RichMinerals)).id();
-                commands.send_event(EntityFound::<MineralPatch>::from(entity));
-                entity
-            }
-            TypeId::VespeneGeyser
-            | TypeId::SpacePlatformGeyser
-            | TypeId::RichVespeneGeyser
-            | TypeId::ProtossVespeneGeyser
-            | TypeId::PurifierVespeneGeyser
-            | TypeId::ShakurasVespeneGeyser => {
-                let entity = VespeneGeyserBundle {
-                    unit: EntityBundle::from(unit.clone()),
-                    ..Default::default()
-                };
-
-                let entity = commands.spawn(entity).id();
-                commands.send_event(EntityFound::<VespeneGeyser>::from(entity));
-                entity
-            }
-            TypeId::DestructibleCityDebris2x4Vertical
-            | TypeId::DestructibleCityDebris2x4Horizontal
-            | TypeId::DestructibleCityDebris2x6Vertical
-            | TypeId::DestructibleCityDebris2x6Horizontal
-            | TypeId::DestructibleCityDebris4x4
-            | TypeId::DestructibleCityDebris6x6
-            | TypeId::DestructibleRockEx12x4Vertical
-            | TypeId::DestructibleRockEx12x4Horizontal
-            | TypeId::DestructibleRockEx12x6Vertical
-            | TypeId::DestructibleRockEx12x6Horizontal
-            | TypeId::DestructibleRockEx14x4
-            | TypeId::DestructibleRockEx16x6
-            | TypeId::UnbuildableRocksDestructible
-            | TypeId::UnbuildableBricksDestructible
-            | TypeId::UnbuildablePlatesDestructible => {
-                let entity = DestructibleRockBundle {
-                    unit: EntityBundle::from(unit.clone()),
-                    ..Default::default()
-                };
-                commands.spawn(entity).id()
-            }
+        mark_revealed(&mut commands, &interface, unit, entity);
+        map.insert(GameId(unit.tag()), entity);
+    }
+}
 
-            TypeId::Hatchery => {
-                let entity = HatcheryBundle {
-                    unit: EntityBundle::from(unit.clone()),
-                    ..Default::default()
-                };
-                commands.spawn(entity).id()
-            }
-            TypeId::Larva => {
-                let entity = LarvaBundle {
-                    unit: EntityBundle::from(unit.clone()),
-                    ..Default::default()
-                };
-                commands.spawn(entity).id()
-            }
-            TypeId::SCV | TypeId::Probe | TypeId::Drone => {
-                let entity = WorkerBundle {
-                    unit: EntityBundle::from(unit.clone()),
-                    ..Default::default()
-                };
+/// Spawn the entity of a unit, returning [`None`] if units of its type aren't handled.
+fn spawn_entity(commands: &mut Commands, unit: &Unit) -> Option<Entity> {
+    use sc2_proto::unit::TypeId;
+    let Some(unit_type) = TypeId::from_u32(unit.unit_type()) else {
+        warn!("Unknown unit type: {}", unit.unit_type());
+        return None;
+    };
 
-                commands.spawn(entity).id()
-            }
-            TypeId::Overlord => {
-                let entity = OverlordBundle {
-                    unit: EntityBundle::from(unit.clone()),
-                    ..Default::default()
-                };
+    let entity = match unit_type {
+        TypeId::MineralField
+        | TypeId::MineralField450
+        | TypeId::MineralField750
+        | TypeId::LabMineralField
+        | TypeId::LabMineralField750
+        | TypeId::PurifierMineralField
+        | TypeId::PurifierMineralField750
+        | TypeId::BattleStationMineralField
+        | TypeId::BattleStationMineralField750 => {
+            let entity = MineralPatchBundle {
+                unit: EntityBundle::from(unit.clone()),
+                ..Default::default()
+            };
 
-                commands.spawn(entity).id()
-            }
+            let entity = commands.spawn(entity).id();
+            commands.send_event(EntityFound::<MineralPatch>::from(entity));
+            entity
+        }
 
-            _ => {
-                warn!("Unhandled unit: {unit:?}");
-                continue;
-            }
-        };
+        TypeId::PurifierRichMineralField
+        | TypeId::PurifierRichMineralField750
+        | TypeId::RichMineralField
+        | TypeId::RichMineralField750 => {
+            let entity = MineralPatchBundle {
+                unit: EntityBundle::from(unit.clone()),
+                ..Default::default()
+            };
 
-        map.insert(GameId(unit.tag()), entity);
+            let entity = commands.spawn((entity, RichMinerals)).id();
+            commands.send_event(EntityFound::<MineralPatch>::from(entity));
+            entity
+        }
+        TypeId::VespeneGeyser
+        | TypeId::SpacePlatformGeyser
+        | TypeId::RichVespeneGeyser
+        | TypeId::ProtossVespeneGeyser
+        | TypeId::PurifierVespeneGeyser
+        | TypeId::ShakurasVespeneGeyser => {
+            let entity = VespeneGeyserBundle {
+                unit: EntityBundle::from(unit.clone()),
+                ..Default::default()
+            };
+
+            let entity = commands.spawn(entity).id();
+            commands.send_event(EntityFound::<VespeneGeyser>::from(entity));
+            entity
+        }
+        TypeId::DestructibleCityDebris2x4Vertical
+        | TypeId::DestructibleCityDebris2x4Horizontal
+        | TypeId::DestructibleCityDebris2x6Vertical
+        | TypeId::DestructibleCityDebris2x6Horizontal
+        | TypeId::DestructibleCityDebris4x4
+        | TypeId::DestructibleCityDebris6x6
+        | TypeId::DestructibleRockEx12x4Vertical
+        | TypeId::DestructibleRockEx12x4Horizontal
+        | TypeId::DestructibleRockEx12x6Vertical
+        | TypeId::DestructibleRockEx12x6Horizontal
+        | TypeId::DestructibleRockEx14x4
+        | TypeId::DestructibleRockEx16x6
+        | TypeId::UnbuildableRocksDestructible
+        | TypeId::UnbuildableBricksDestructible
+        | TypeId::UnbuildablePlatesDestructible => {
+            let entity = DestructibleRockBundle {
+                unit: EntityBundle::from(unit.clone()),
+                ..Default::default()
+            };
+            commands.spawn(entity).id()
+        }
+
+        TypeId::Hatchery => {
+            let entity = HatcheryBundle {
+                unit: EntityBundle::from(unit.clone()),
+                ..Default::default()
+            };
+            commands.spawn(entity).id()
+        }
+        TypeId::Larva => {
+            let entity = LarvaBundle {
+                unit: EntityBundle::from(unit.clone()),
+                ..Default::default()
+            };
+            commands.spawn(entity).id()
+        }
+        TypeId::SCV | TypeId::Probe | TypeId::Drone => {
+            let entity = WorkerBundle {
+                unit: EntityBundle::from(unit.clone()),
+                ..Default::default()
+            };
+
+            commands.spawn(entity).id()
+        }
+        TypeId::Overlord => {
+            let entity = OverlordBundle {
+                unit: EntityBundle::from(unit.clone()),
+                ..Default::default()
+            };
+
+            commands.spawn(entity).id()
+        }
+
+        _ => return None,
+    };
+
+    Some(entity)
+}
+
+/// Mark a newly spawned enemy entity as [`Revealed`] if it may only be seen because fog of war is
+/// disabled. Fog of war stays disabled for the whole game, so this never changes.
+fn mark_revealed(
+    commands: &mut Commands,
+    interface: &InterfaceConfig,
+    unit: &Unit,
+    entity: Entity,
+) {
+    if interface.disable_fog && unit.alliance() == Alliance::Enemy {
+        commands.entity(entity).insert(Revealed);
     }
 }
 
+/// Update entities from the [`Observation`] resource, creating those of units seen for the first
+/// time.
+///
+/// Units of types unknown to the API version these bindings were generated from are skipped, with
+/// a warning the first time each type is seen.
 fn update_entities(
     mut commands: Commands,
     observation: Res<ApiObservation>,
-    map: Res<EntityIdMap>,
+    interface: Res<InterfaceConfig>,
+    mut map: ResMut<EntityIdMap>,
+    mut unknown_types: Local<HashSet<u32>>,
 ) {
     for unit in &observation.units {
         use sc2_proto::unit::TypeId;
-        let unit_type = TypeId::from_u32(unit.unit_type()).unwrap();
+
+        let Some(unit_type) = TypeId::from_u32(unit.unit_type()) else {
+            if unknown_types.insert(unit.unit_type()) {
+                warn!("Unknown unit type: {}", unit.unit_type());
+            }
+            continue;
+        };
+
+        // Units may first be seen partway through the game, e.g. once created or when enemy units
+        // come into vision.
+        let Some(&entity) = map.get(&GameId(unit.tag())) else {
+            if let Some(entity) = spawn_entity(&mut commands, unit) {
+                mark_revealed(&mut commands, &interface, unit, entity);
+                map.insert(GameId(unit.tag()), entity);
+            }
+            continue;
+        };
 
         match unit_type {
             TypeId::MineralField | TypeId::MineralField450 | TypeId::MineralField750 => {}
@@ -227,10 +305,7 @@ fn update_entities(
             TypeId::Hatchery => {}
 
             TypeId::Larva | TypeId::Overlord | TypeId::SCV | TypeId::Probe | TypeId::Drone => {
-                let entity = map
-                    .get(&GameId(unit.tag()))
-                    .expect("Missing entity in EntityIdMap");
-                let mut entity = commands.entity(*entity);
+                let mut entity = commands.entity(entity);
                 entity.insert(Vec3::from(unit.pos.get_or_default().clone()));
             }
 
@@ -0,0 +1,154 @@
+//! Queries to the game for pathing distances, building placement and available abilities.
+//!
+//! Each query returns a [`QueryId`] immediately. Queries made during a tick are sent together
+//! before the game is stepped and their results dispatched as [`QueryResult`] events, readable
+//! from the following tick.
+//!
+//! [`QueryResult`]: crate::core::QueryResult
+
+use bevy::ecs::{
+    entity::Entity,
+    world::{Command, World},
+};
+use num_traits::ToPrimitive;
+
+use sc2_proto::query::{
+    RequestQueryAvailableAbilities, RequestQueryBuildingPlacement, RequestQueryPathing,
+};
+
+use crate::{
+    core::{Queries, QueryId},
+    game::{
+        entity::{BuildingEntity, game_id},
+        geometry::Vec2,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Query {
+    Path {
+        from: PathStart,
+        to: Vec2,
+    },
+    Placement {
+        ability: sc2_proto::AbilityId,
+        location: Vec2,
+        builder: Option<Entity>,
+    },
+    Abilities {
+        unit: Entity,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PathStart {
+    Position(Vec2),
+    Unit(Entity),
+}
+
+struct QueryCommand {
+    id: QueryId,
+    query: Query,
+}
+
+impl Command for QueryCommand {
+    fn apply(self, world: &mut World) {
+        match self.query {
+            Query::Path { from, to } => {
+                let mut query = RequestQueryPathing::new();
+                match from {
+                    PathStart::Position(pos) => query.set_start_pos(pos.into()),
+                    PathStart::Unit(unit) => query.set_unit_tag(game_id(world, unit)),
+                }
+                query.end_pos = protobuf::MessageField::some(to.into());
+
+                world.resource_mut::<Queries>().pathing(self.id, query);
+            }
+            Query::Placement {
+                ability,
+                location,
+                builder,
+            } => {
+                let mut query = RequestQueryBuildingPlacement::new();
+                query.set_ability_id(ability.to_i32().unwrap());
+                query.target_pos = protobuf::MessageField::some(location.into());
+                query.placing_unit_tag = builder.map(|unit| game_id(world, unit));
+
+                world.resource_mut::<Queries>().placement(self.id, query);
+            }
+            Query::Abilities { unit } => {
+                let mut query = RequestQueryAvailableAbilities::new();
+                query.set_unit_tag(game_id(world, unit));
+
+                world.resource_mut::<Queries>().abilities(self.id, query);
+            }
+        }
+    }
+}
+
+pub trait QueryCommandsExt {
+    /// Query the distance along the shortest ground path between two points.
+    fn query_path(&mut self, from: Vec2, to: Vec2) -> QueryId;
+
+    /// Query the distance along the shortest path a unit could take to a point.
+    fn query_unit_path(&mut self, unit: Entity, to: Vec2) -> QueryId;
+
+    /// Query whether a building can be placed at a location, optionally by a specific builder.
+    fn query_placement<T>(&mut self, location: Vec2, builder: Option<Entity>) -> QueryId
+    where
+        T: BuildingEntity;
+
+    /// Query the abilities a unit can currently use.
+    fn query_abilities(&mut self, unit: Entity) -> QueryId;
+}
+
+impl QueryCommandsExt for bevy::ecs::system::Commands<'_, '_> {
+    fn query_path(&mut self, from: Vec2, to: Vec2) -> QueryId {
+        let id = QueryId::next();
+        self.queue(QueryCommand {
+            id,
+            query: Query::Path {
+                from: PathStart::Position(from),
+                to,
+            },
+        });
+        id
+    }
+
+    fn query_unit_path(&mut self, unit: Entity, to: Vec2) -> QueryId {
+        let id = QueryId::next();
+        self.queue(QueryCommand {
+            id,
+            query: Query::Path {
+                from: PathStart::Unit(unit),
+                to,
+            },
+        });
+        id
+    }
+
+    fn query_placement<T>(&mut self, location: Vec2, builder: Option<Entity>) -> QueryId
+    where
+        T: BuildingEntity,
+    {
+        let id = QueryId::next();
+        self.queue(QueryCommand {
+            id,
+            query: Query::Placement {
+                ability: T::BUILD_ID,
+                location,
+                builder,
+            },
+        });
+        id
+    }
+
+    fn query_abilities(&mut self, unit: Entity) -> QueryId {
+        let id = QueryId::next();
+        self.queue(QueryCommand {
+            id,
+            query: Query::Abilities { unit },
+        });
+        id
+    }
+}
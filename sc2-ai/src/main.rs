@@ -1,7 +1,12 @@
-use std::net::Ipv4Addr;
+use std::{
+    net::Ipv4Addr,
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    time::Duration,
+};
 
 use bevy::{
-    app::{App, Update},
+    app::{App, AppExit, Last, Update},
     ecs::{
         entity::Entity,
         event::EventReader,
@@ -14,11 +19,18 @@ use clap::Parser;
 use tracing::{info, warn};
 
 mod ai;
+mod config;
 mod core;
 mod game;
+mod scenario;
+mod series;
 
 use ai::AiPluginGroup;
-use core::{CorePlugin, StartupMode};
+use config::Config;
+use core::{
+    CorePlugin, FeatureLayerConfig, INTERRUPTED_EXIT_CODE, InterfaceConfig, Metrics, MockGame,
+    OpponentType, RequestKind, StartupMode, TraceConfig, TraceFormat,
+};
 use game::{
     GamePlugin,
     action::{ActionCommandsExt, MoveEvent},
@@ -31,23 +43,170 @@ use game::{
     geometry::{Cuboid, Line2, Vec2, Vec3},
     map::PlacementGrid,
 };
+use scenario::{Scenario, ScenarioPlugin};
+use series::{Series, Summary};
 
 #[derive(Parser, Clone, Debug, PartialEq, Eq)]
 struct Args {
-    #[arg(long = "start-process")]
+    /// TOML file containing any of the game setup options.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    setup: Config,
+
+    #[arg(long = "start-process", conflicts_with_all = ["mock", "playback", "game_port"])]
     start_process: bool,
 
+    /// Launch two game instances and play the bot against itself. Equivalent to
+    /// `--opponent participant`.
+    #[arg(long, conflicts_with_all = ["start_process", "mock", "playback", "game_port"])]
+    versus: bool,
+
+    /// Play a series of games against computer opponents, defined in a TOML file. Games can't be
+    /// recorded or traced, as each would overwrite the last.
+    #[arg(long, conflicts_with_all = ["versus", "playback", "game_port", "record", "trace"])]
+    series: Option<PathBuf>,
+
+    /// Run a scenario test defined in a TOML file, exiting with an error if any assertion fails.
+    #[arg(long, conflicts_with_all = ["versus", "series", "playback", "game_port"])]
+    scenario: Option<PathBuf>,
+
+    /// Observe a replay instead of playing, running the bot's systems over it without sending any
+    /// actions.
+    #[arg(long, conflicts_with_all = ["start_process", "versus", "series", "scenario", "mock", "playback", "game_port", "replay_dir"])]
+    replay: Option<PathBuf>,
+
+    /// Player whose perspective a replay is observed from.
+    #[arg(long = "observed-player", default_value_t = 1, requires = "replay")]
+    observed_player: u32,
+
+    /// Run against an in-process mock of the game API instead of StarCraft II.
+    #[arg(long, conflicts_with_all = ["playback", "game_port"])]
+    mock: bool,
+
+    /// Record the request/response stream of the game to a file.
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Write a readable trace of every request and response to a file, rotated once it grows too
+    /// large.
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    #[arg(long = "trace-format", value_enum, default_value_t, requires = "trace")]
+    trace_format: TraceFormat,
+
+    /// Only trace these kinds of request.
+    #[arg(
+        long = "trace-only",
+        value_enum,
+        value_delimiter = ',',
+        requires = "trace"
+    )]
+    trace_only: Vec<RequestKind>,
+
+    /// Trace these kinds of request without their contents, e.g. `observation`.
+    #[arg(
+        long = "trace-skip-bodies",
+        value_enum,
+        value_delimiter = ',',
+        requires = "trace"
+    )]
+    trace_skip_bodies: Vec<RequestKind>,
+
+    /// Truncate traced messages longer than this many bytes.
+    #[arg(long = "trace-max-message", requires = "trace")]
+    trace_max_message: Option<usize>,
+
+    /// Append a JSON summary of request, schedule and step timings to a file at the end of each
+    /// game.
+    #[arg(long)]
+    metrics: Option<PathBuf>,
+
+    /// Also write the timings in the Prometheus text format to a file.
+    #[arg(long = "metrics-prometheus", requires = "metrics")]
+    metrics_prometheus: Option<PathBuf>,
+
+    /// Directory to save a replay of each game to.
+    #[arg(long = "replay-dir", conflicts_with = "playback")]
+    replay_dir: Option<PathBuf>,
+
+    /// Play back a recording made with `--record` instead of connecting to the game.
+    #[arg(long)]
+    playback: Option<PathBuf>,
+
     #[arg(long, default_value_t = 8167)]
     port: u16,
 
+    /// Seconds to wait for the game to accept a connection.
+    #[arg(long = "connect-timeout", default_value_t = CorePlugin::DEFAULT_CONNECT_TIMEOUT.as_secs())]
+    connect_timeout: u64,
+
     #[arg(long = "step-rate", group = "step-rate", default_value_t = 22)]
     step_rate: u64,
 
-    #[arg(long, group = "step-rate")]
+    #[arg(long, group = "step-rate", alias = "RealTime")]
     realtime: bool,
 
-    #[arg(short, long)]
-    map: String,
+    /// Game loops to step the game by each update.
+    #[arg(long = "step-count", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "realtime")]
+    step_count: u32,
+
+    /// Step the game while the bot updates rather than between updates. Actions take effect a step
+    /// later.
+    #[arg(long, conflicts_with = "realtime")]
+    pipeline: bool,
+
+    /// Observe the whole map, ignoring fog of war. Enemy entities seen this way are marked as
+    /// revealed.
+    #[arg(long = "disable-fog")]
+    disable_fog: bool,
+
+    /// Give coordinates relative to the playable area of the map.
+    #[arg(long = "crop-to-playable-area")]
+    crop_to_playable_area: bool,
+
+    /// Also observe feature layers, rendering the screen and minimap at the given square sizes in
+    /// pixels.
+    #[arg(long = "feature-layers", num_args = 2, value_names = ["SCREEN", "MINIMAP"])]
+    feature_layers: Option<Vec<u32>>,
+
+    /// Number of games to play in a row. Games after the first are restarted within the same game
+    /// instances.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    games: u32,
+
+    /// Ladder: port of the game instance to connect to.
+    #[arg(long = "GamePort", requires_all = ["start_port", "ladder_server"])]
+    game_port: Option<u16>,
+
+    /// Ladder: first port of the range reserved for the game's multiplayer ports.
+    #[arg(long = "StartPort")]
+    start_port: Option<u16>,
+
+    /// Ladder: address of the game instance to connect to.
+    #[arg(long = "LadderServer")]
+    ladder_server: Option<Ipv4Addr>,
+
+    /// Ladder: identifier of the opponent.
+    #[arg(long = "OpponentId")]
+    opponent_id: Option<String>,
+}
+
+impl Args {
+    /// Options for tracing requests, if enabled.
+    fn trace_config(&self) -> Option<TraceConfig> {
+        let path = self.trace.clone()?;
+
+        Some(TraceConfig {
+            format: self.trace_format,
+            only: self.trace_only.clone(),
+            skip_bodies: self.trace_skip_bodies.clone(),
+            max_message_len: self.trace_max_message,
+            ..TraceConfig::new(path)
+        })
+    }
 }
 
 fn main() -> Result<(), anyhow::Error> {
@@ -60,19 +219,233 @@ fn main() -> Result<(), anyhow::Error> {
 
     let args = Args::parse();
 
-    let core = if args.start_process {
-        CorePlugin::new(StartupMode::Launch, args.map, args.realtime)
+    let config = match &args.config {
+        Some(path) => args.setup.clone().or(Config::load(path)?),
+        None => args.setup.clone(),
+    };
+    let setup = config.game_setup();
+    let versus = args.replay.is_none()
+        && (args.versus
+            || matches!(
+                setup.opponent,
+                OpponentType::Participant | OpponentType::Observer
+            ));
+    let interface = InterfaceConfig {
+        disable_fog: args.disable_fog,
+        crop_to_playable_area: args.crop_to_playable_area,
+        feature_layers: args
+            .feature_layers
+            .as_deref()
+            .map(|sizes| FeatureLayerConfig::new(sizes[0], sizes[1])),
+        ..Default::default()
+    };
+
+    let mode = if let Some(path) = args.replay.clone() {
+        StartupMode::Replay {
+            path,
+            player_id: args.observed_player,
+        }
+    } else if args.start_process || versus {
+        StartupMode::Launch
+    } else if args.mock {
+        StartupMode::Mock {
+            game_length: Some(MockGame::DEFAULT_GAME_LENGTH),
+        }
+    } else if let Some(path) = args.playback.clone() {
+        StartupMode::Playback { path }
+    } else if let (Some(game_port), Some(start_port), Some(server)) =
+        (args.game_port, args.start_port, args.ladder_server)
+    {
+        StartupMode::Ladder {
+            server,
+            game_port,
+            start_port,
+            opponent_id: args.opponent_id.clone(),
+        }
     } else {
-        CorePlugin::new(
-            StartupMode::Connect {
-                addr: Ipv4Addr::new(127, 0, 0, 1),
-                port: args.port,
-            },
-            args.map,
-            args.realtime,
-        )
+        StartupMode::Connect {
+            addr: Ipv4Addr::new(127, 0, 0, 1),
+            port: args.port,
+        }
+    };
+
+    if let Some(path) = &args.series {
+        let series = Series::load(path)?;
+        let mut summary = Summary::default();
+
+        for matchup in series.matchups(&setup) {
+            info!("Playing {matchup}");
+            let mut core = CorePlugin::new(mode.clone(), matchup.map.clone(), args.realtime)
+                .with_connect_timeout(Duration::from_secs(args.connect_timeout))
+                .with_setup(matchup.setup.clone())
+                .with_interface(interface)
+                .with_step_count(args.step_count)
+                .with_pipelining(args.pipeline)
+                .with_games(series.games);
+            if let Some(dir) = args.replay_dir.clone() {
+                core = core.with_replay_dir(dir);
+            }
+            if let Some(path) = args.metrics.clone() {
+                core = core.with_metrics(path, args.metrics_prometheus.clone());
+            }
+
+            let results = Arc::new(Mutex::new(Vec::new()));
+            let mut app = build_app(core);
+            app.add_systems(Last, series::collect_results(results.clone()));
+            app.set_runner(runner(args.step_rate, args.realtime, None));
+            let exit = app.run();
+
+            let results = results.lock().map(|r| r.clone()).unwrap_or_default();
+            summary.record(&matchup, &results);
+
+            // Written after every matchup so that results aren't lost if the series is cut short.
+            summary.write(&series.summary)?;
+
+            if exit == AppExit::from_code(INTERRUPTED_EXIT_CODE) {
+                warn!("Series interrupted");
+                exit_with_code(exit);
+            }
+        }
+
+        info!(
+            "Series finished, summary written to {}",
+            series.summary.display()
+        );
+        return Ok(());
+    }
+
+    if let Some(path) = &args.scenario {
+        let scenario = Scenario::load(path)?;
+        let map = scenario
+            .map
+            .clone()
+            .or(config.map)
+            .ok_or_else(|| anyhow::anyhow!("A map must be given to run the scenario on"))?;
+
+        // The mock game would otherwise end before longer scenarios finish.
+        let mode = match mode {
+            StartupMode::Mock { .. } => StartupMode::Mock { game_length: None },
+            mode => mode,
+        };
+
+        let mut core = CorePlugin::new(mode, map, args.realtime)
+            .with_connect_timeout(Duration::from_secs(args.connect_timeout))
+            .with_setup(setup)
+            .with_interface(interface)
+            .with_step_count(args.step_count)
+            .with_pipelining(args.pipeline);
+        if let Some(path) = args.record.clone() {
+            core = core.with_recording(path);
+        }
+        if let Some(config) = args.trace_config() {
+            core = core.with_trace(config);
+        }
+        if let Some(dir) = args.replay_dir.clone() {
+            core = core.with_replay_dir(dir);
+        }
+        if let Some(path) = args.metrics.clone() {
+            core = core.with_metrics(path, args.metrics_prometheus.clone());
+        }
+
+        let report = Arc::new(Mutex::new(None));
+        let mut app = App::new();
+        app.add_plugins(core)
+            .add_plugins(GamePlugin)
+            .add_plugins(ScenarioPlugin::new(scenario, report.clone()));
+        app.set_runner(runner(args.step_rate, args.realtime, None));
+        let exit = app.run();
+        if exit == AppExit::from_code(INTERRUPTED_EXIT_CODE) {
+            exit_with_code(exit);
+        }
+
+        let report = report.lock().ok().and_then(|report| report.clone());
+        return match report {
+            Some(report) if report.failures.is_empty() => {
+                info!("Scenario passed");
+                Ok(())
+            }
+            Some(report) => anyhow::bail!("Scenario failed: {}", report.failures.join("; ")),
+            None => anyhow::bail!("Scenario didn't run to completion"),
+        };
+    }
+
+    let map = match (&mode, config.map) {
+        (_, Some(map)) => map,
+        (StartupMode::Launch | StartupMode::Connect { .. }, None) => {
+            anyhow::bail!("A map must be given to create a game on")
+        }
+        (_, None) => String::new(),
     };
 
+    let mut core = CorePlugin::new(mode, map, args.realtime)
+        .with_connect_timeout(Duration::from_secs(args.connect_timeout))
+        .with_setup(setup)
+        .with_interface(interface)
+        .with_step_count(args.step_count)
+        .with_pipelining(args.pipeline)
+        .with_games(args.games);
+    if let Some(path) = args.record.clone() {
+        core = core.with_recording(path);
+    }
+    if let Some(config) = args.trace_config() {
+        core = core.with_trace(config);
+    }
+    if let Some(dir) = args.replay_dir.clone() {
+        core = core.with_replay_dir(dir);
+    }
+    if let Some(path) = args.metrics.clone() {
+        core = core.with_metrics(path, args.metrics_prometheus.clone());
+    }
+
+    if versus {
+        let exits = std::thread::scope(|scope| {
+            let threads = core
+                .start_versus()?
+                .into_iter()
+                .zip(Lockstep::handles(2))
+                .map(|(core, lockstep)| {
+                    scope.spawn(move || {
+                        let mut app = build_app(core);
+                        app.set_runner(runner(args.step_rate, args.realtime, Some(lockstep)));
+                        app.run()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Ok::<_, anyhow::Error>(
+                threads
+                    .into_iter()
+                    .map(|thread| {
+                        thread
+                            .join()
+                            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })?;
+
+        info!("Versus game finished: {exits:?}");
+        exits.into_iter().for_each(exit_with_code);
+        return Ok(());
+    }
+
+    let mut app = build_app(core);
+    app.set_runner(runner(args.step_rate, args.realtime, None));
+
+    info!("Running game");
+    exit_with_code(app.run());
+
+    Ok(())
+}
+
+/// Exit the process with the app's exit code if it failed or was interrupted.
+fn exit_with_code(exit: AppExit) {
+    if let AppExit::Error(code) = exit {
+        std::process::exit(code.get().into());
+    }
+}
+
+fn build_app(core: CorePlugin) -> App {
     info!("Setting up ECS");
 
     let mut app = App::new();
@@ -94,28 +467,132 @@ fn main() -> Result<(), anyhow::Error> {
             .chain(),
     );
 
-    app.set_runner(move |mut app| {
-        let step_period = std::time::Duration::from_millis(1000 / args.step_rate);
+    app
+}
+
+/// Synchronises the updates of apps running on separate threads.
+///
+/// Each app holds its own handle. Dropping a handle, including when its app panics, counts as that
+/// app exiting, so the others stop waiting for it and exit after their current update.
+#[derive(Debug)]
+struct Lockstep {
+    shared: Arc<(Mutex<LockstepState>, Condvar)>,
+}
+
+#[derive(Debug)]
+struct LockstepState {
+    /// Apps whose handles are still held.
+    running: usize,
+    /// Apps which have finished the current update.
+    arrived: usize,
+    update: u64,
+    exit: bool,
+    /// Update after which every app exits, once decided.
+    exit_after: Option<u64>,
+}
+
+impl Lockstep {
+    fn handles(apps: usize) -> Vec<Self> {
+        let state = LockstepState {
+            running: apps,
+            arrived: 0,
+            update: 0,
+            exit: false,
+            exit_after: None,
+        };
+        let shared = Arc::new((Mutex::new(state), Condvar::new()));
+
+        (0..apps)
+            .map(|_| Self {
+                shared: shared.clone(),
+            })
+            .collect()
+    }
+
+    /// Wait for every app to finish the current update, returning whether they should all exit.
+    /// They exit together if any of them wants to.
+    fn finish_update(&self, exit: bool) -> bool {
+        let (state, condvar) = &*self.shared;
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let update = state.update;
+        state.exit |= exit;
+        state.arrived += 1;
+        state.release(condvar);
+
+        let state = condvar
+            .wait_while(state, |state| state.update == update)
+            .unwrap_or_else(PoisonError::into_inner);
+        state.exit_after == Some(update)
+    }
+}
+
+impl Drop for Lockstep {
+    fn drop(&mut self) {
+        let (state, condvar) = &*self.shared;
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        state.running -= 1;
+        state.exit = true;
+        state.release(condvar);
+    }
+}
+
+impl LockstepState {
+    /// Move on to the next update once every app still running has finished the current one.
+    fn release(&mut self, condvar: &Condvar) {
+        if self.arrived == 0 || self.arrived < self.running {
+            return;
+        }
+
+        if self.exit {
+            self.exit_after = Some(self.update);
+        }
+        self.arrived = 0;
+        self.update += 1;
+        condvar.notify_all();
+    }
+}
+
+fn runner(
+    step_rate: u64,
+    realtime: bool,
+    lockstep: Option<Lockstep>,
+) -> impl FnOnce(App) -> AppExit {
+    move |mut app| {
+        let step_period = std::time::Duration::from_millis(1000 / step_rate);
         let mut next_step = std::time::Instant::now() + step_period;
 
         loop {
-            if !args.realtime {
+            // In realtime, updates are instead paced by the game as each observation waits for
+            // the next game loop.
+            if !realtime {
                 let now = std::time::Instant::now();
                 std::thread::sleep(next_step.duration_since(now));
                 next_step = now + step_period;
             }
 
+            let start = std::time::Instant::now();
             app.update();
-            if let Some(exit) = app.should_exit() {
-                return exit;
+
+            // Steps aren't expected to keep to the step period in realtime.
+            let period = (!realtime).then_some(step_period);
+            if let Some(mut metrics) = app.world_mut().get_resource_mut::<Metrics>() {
+                metrics.record_step(start.elapsed(), period);
             }
-        }
-    });
 
-    info!("Running game");
-    app.run();
+            let exit = app.should_exit();
 
-    Ok(())
+            // Every app must finish the update before any of them exit.
+            if let Some(lockstep) = &lockstep {
+                if lockstep.finish_update(exit.is_some()) {
+                    return exit.unwrap_or(AppExit::Success);
+                }
+            } else if let Some(exit) = exit {
+                return exit;
+            }
+        }
+    }
 }
 
 fn move_workers(mut commands: Commands, query: Query<Entity, With<Worker>>) {
@@ -153,3 +630,52 @@ fn draw_move_actions(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn series_rejects_recording_and_tracing() {
+        let series = ["sc2-ai", "--series", "series.toml"];
+        assert!(Args::try_parse_from(series).is_ok());
+
+        for option in ["--record", "--trace"] {
+            let args = series.into_iter().chain([option, "out"]);
+            assert!(Args::try_parse_from(args).is_err(), "{option}");
+        }
+    }
+
+    #[test]
+    fn lockstep_exits_together() {
+        let [first, second] = <[Lockstep; 2]>::try_from(Lockstep::handles(2)).unwrap();
+
+        let exits = std::thread::scope(|scope| {
+            let first =
+                scope.spawn(move || [first.finish_update(false), first.finish_update(true)]);
+            let second =
+                scope.spawn(move || [second.finish_update(false), second.finish_update(false)]);
+            [first.join().unwrap(), second.join().unwrap()]
+        });
+
+        assert_eq!(exits, [[false, true], [false, true]]);
+    }
+
+    #[test]
+    fn lockstep_stops_waiting_for_dropped_app() {
+        let [first, second] = <[Lockstep; 2]>::try_from(Lockstep::handles(2)).unwrap();
+
+        let exit = std::thread::scope(|scope| {
+            let waiting = scope.spawn(move || first.finish_update(false));
+            let failed = scope.spawn(move || {
+                let _second = second;
+                panic!("App failed");
+            });
+
+            assert!(failed.join().is_err());
+            waiting.join().unwrap()
+        });
+
+        assert!(exit);
+    }
+}
@@ -0,0 +1,342 @@
+//! Scenario tests of micro behaviour.
+//!
+//! A scenario spawns units for each player through debug commands, runs the game for a number of
+//! game loops and then checks assertions against the state of the game.
+//!
+//! The map isn't cleared before spawning, so scenarios expect a map without any units of the
+//! players' own, as any there would count towards the assertions. A warning is logged if there are
+//! any. The mock game's map holds a base for player 1.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    app::{App, AppExit, Plugin, Startup, Update},
+    ecs::{
+        event::EventWriter,
+        system::{Commands, Res, ResMut, Resource},
+    },
+};
+use num_traits::FromPrimitive as _;
+use serde::{Deserialize, Deserializer, de::Error as _};
+use tracing::{error, info, warn};
+
+use sc2_proto::{raw::Alliance, unit::TypeId};
+
+use crate::{
+    core::{ApiObservation, GameTime, Score},
+    game::{debug::DebugCommandsExt as _, geometry::Vec2},
+};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Scenario {
+    /// Map to play on. The map given on the command line is used if not set.
+    #[serde(default)]
+    pub map: Option<String>,
+
+    /// Game loops to run for after spawning units.
+    pub loops: u32,
+
+    #[serde(default, rename = "spawn")]
+    pub spawns: Vec<Spawn>,
+
+    #[serde(default, rename = "assert")]
+    pub assertions: Vec<Assertion>,
+}
+
+/// Units to spawn at the start of the scenario.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Spawn {
+    pub player: u32,
+    pub unit: UnitType,
+    pub x: f32,
+    pub y: f32,
+    #[serde(default = "Spawn::default_count")]
+    pub count: u32,
+}
+
+impl Spawn {
+    fn default_count() -> u32 {
+        1
+    }
+}
+
+/// Checks made against the state of the game at the end of the scenario.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Assertion {
+    /// Number of a player's units of a type which are alive.
+    UnitsAlive {
+        player: u32,
+        unit: UnitType,
+        #[serde(default)]
+        min: u32,
+        max: Option<u32>,
+    },
+
+    /// Number of a player's units, optionally of a type, within `radius` of a point.
+    UnitsNear {
+        player: u32,
+        unit: Option<UnitType>,
+        x: f32,
+        y: f32,
+        radius: f32,
+        min: u32,
+    },
+
+    /// Damage dealt by us to enemy life and shields.
+    DamageDealt { min: f32 },
+}
+
+/// Unit type given by name, e.g. `"Marine"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnitType(pub TypeId);
+
+impl<'de> Deserialize<'de> for UnitType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+
+        (0..=u32::from(u16::MAX))
+            .filter_map(TypeId::from_u32)
+            .find(|id| format!("{id:?}") == name)
+            .map(UnitType)
+            .ok_or_else(|| D::Error::custom(format!("Unknown unit type: {name}")))
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+impl Assertion {
+    /// Check the assertion, returning a description of the failure if it doesn't hold.
+    fn check(&self, observation: &ApiObservation, score: &Score) -> Result<(), String> {
+        let count_units = |player: u32, unit: Option<UnitType>, near: Option<(Vec2, f32)>| {
+            observation
+                .units
+                .iter()
+                .filter(|u| u.owner() == player as i32)
+                .filter(|u| {
+                    unit.is_none_or(|UnitType(id)| TypeId::from_u32(u.unit_type()) == Some(id))
+                })
+                .filter(|u| {
+                    near.is_none_or(|(point, radius)| {
+                        Vec2::new(u.pos.x(), u.pos.y()).distance(point) <= radius
+                    })
+                })
+                .count() as u32
+        };
+
+        match *self {
+            Assertion::UnitsAlive {
+                player,
+                unit,
+                min,
+                max,
+            } => {
+                let count = count_units(player, Some(unit), None);
+                if count < min || max.is_some_and(|max| count > max) {
+                    return Err(format!(
+                        "Player {player} has {count} {:?} alive, expected {min} to {}",
+                        unit.0,
+                        max.map_or("any".to_owned(), |max| max.to_string())
+                    ));
+                }
+            }
+            Assertion::UnitsNear {
+                player,
+                unit,
+                x,
+                y,
+                radius,
+                min,
+            } => {
+                let count = count_units(player, unit, Some((Vec2::new(x, y), radius)));
+                if count < min {
+                    return Err(format!(
+                        "Player {player} has {count} units within {radius} of ({x}, {y}), expected at least {min}"
+                    ));
+                }
+            }
+            Assertion::DamageDealt { min } => {
+                let damage = &score.score_details.total_damage_dealt;
+                let dealt = damage.life() + damage.shields();
+                if dealt < min {
+                    return Err(format!("Dealt {dealt} damage, expected at least {min}"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Outcome of a scenario.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub passed: usize,
+    pub failures: Vec<String>,
+}
+
+/// Plays a [`Scenario`], writing the [`Report`] once the scenario has run.
+#[derive(Debug)]
+pub struct ScenarioPlugin {
+    scenario: Scenario,
+    report: Arc<Mutex<Option<Report>>>,
+}
+
+impl ScenarioPlugin {
+    pub fn new(scenario: Scenario, report: Arc<Mutex<Option<Report>>>) -> Self {
+        Self { scenario, report }
+    }
+}
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ScenarioState {
+            scenario: self.scenario.clone(),
+            end_loop: 0,
+            report: self.report.clone(),
+        });
+
+        app.add_systems(Startup, ScenarioState::spawn_units);
+        app.add_systems(Update, ScenarioState::check);
+    }
+}
+
+#[derive(Resource, Debug)]
+struct ScenarioState {
+    scenario: Scenario,
+    end_loop: u32,
+    report: Arc<Mutex<Option<Report>>>,
+}
+
+impl ScenarioState {
+    fn spawn_units(
+        mut commands: Commands,
+        mut state: ResMut<Self>,
+        time: Res<GameTime>,
+        observation: Res<ApiObservation>,
+    ) {
+        let existing = observation
+            .units
+            .iter()
+            .filter(|unit| matches!(unit.alliance(), Alliance::Self_ | Alliance::Enemy))
+            .count();
+        if existing > 0 {
+            warn!("Map already has {existing} player units, which count towards assertions");
+        }
+
+        for spawn in &state.scenario.spawns {
+            let position = Vec2::new(spawn.x, spawn.y);
+            commands.create_units(spawn.unit.0, spawn.player, position, spawn.count);
+        }
+
+        state.end_loop = time.game_loop() + state.scenario.loops;
+        info!("Running scenario until game loop {}", state.end_loop);
+    }
+
+    fn check(
+        state: Res<Self>,
+        time: Res<GameTime>,
+        observation: Res<ApiObservation>,
+        score: Res<Score>,
+        mut exit: EventWriter<AppExit>,
+    ) {
+        if time.game_loop() < state.end_loop {
+            return;
+        }
+
+        let mut report = Report::default();
+        for assertion in &state.scenario.assertions {
+            match assertion.check(&observation, &score) {
+                Ok(()) => report.passed += 1,
+                Err(failure) => {
+                    error!("Assertion failed: {failure}");
+                    report.failures.push(failure);
+                }
+            }
+        }
+
+        info!(
+            "Scenario finished: {} passed, {} failed",
+            report.passed,
+            report.failures.len()
+        );
+
+        if let Ok(mut result) = state.report.lock() {
+            *result = Some(report);
+        }
+        exit.send(AppExit::Success);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        core::{CorePlugin, StartupMode},
+        game::GamePlugin,
+    };
+
+    use super::*;
+
+    fn run_against_mock(scenario: Scenario) -> Report {
+        let map = scenario.map.clone().unwrap_or_else(|| "Mock".to_owned());
+        let mode = StartupMode::Mock { game_length: None };
+        let core = CorePlugin::new(mode, map, false).with_step_count(50);
+
+        let report = Arc::new(Mutex::new(None));
+        let mut app = App::new();
+        app.add_plugins(core)
+            .add_plugins(GamePlugin)
+            .add_plugins(ScenarioPlugin::new(scenario, report.clone()));
+
+        let exit = (0..100).find_map(|_| {
+            app.update();
+            app.should_exit()
+        });
+        assert_eq!(exit, Some(AppExit::Success));
+
+        report.lock().unwrap().clone().unwrap()
+    }
+
+    #[test]
+    fn passes_example_against_mock() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenarios/mock-zerglings.toml");
+        let report = run_against_mock(Scenario::load(&path).unwrap());
+
+        assert_eq!(report.passed, 2);
+        assert!(report.failures.is_empty());
+    }
+
+    #[test]
+    fn reports_failed_assertions() {
+        // The mock game never deals damage.
+        let scenario = toml::from_str(
+            r#"
+            loops = 100
+
+            [[assert]]
+            kind = "damage-dealt"
+            min = 1.0
+            "#,
+        )
+        .unwrap();
+        let report = run_against_mock(scenario);
+
+        assert_eq!(report.passed, 0);
+        assert_eq!(report.failures.len(), 1);
+        assert!(report.failures[0].starts_with("Dealt 0 damage"));
+    }
+}
@@ -0,0 +1,301 @@
+//! Series of games against computer opponents, summarising the results of each matchup.
+
+use std::{
+    borrow::Cow,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bevy::ecs::event::EventReader;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::core::{AiBuild, Difficulty, GameResult, GameSetup, OpponentType, Outcome, Race};
+
+/// Series definition, loaded from a TOML file.
+///
+/// Every combination of map, opponent race, difficulty and AI build is played.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Series {
+    pub maps: Vec<String>,
+
+    #[serde(default = "Series::default_races")]
+    pub races: Vec<Race>,
+
+    #[serde(default = "Series::default_difficulties")]
+    pub difficulties: Vec<Difficulty>,
+
+    #[serde(default = "Series::default_ai_builds")]
+    pub ai_builds: Vec<AiBuild>,
+
+    /// Games to play of each matchup.
+    #[serde(default = "Series::default_games")]
+    pub games: u32,
+
+    /// File to write the summary to. Written as JSON if it has a `.json` extension, otherwise CSV.
+    pub summary: PathBuf,
+}
+
+/// A map and opponent to play a number of games against.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Matchup {
+    pub map: String,
+    pub setup: GameSetup,
+}
+
+impl Series {
+    fn default_races() -> Vec<Race> {
+        vec![Race::Terran, Race::Zerg, Race::Protoss]
+    }
+
+    fn default_difficulties() -> Vec<Difficulty> {
+        vec![Difficulty::default()]
+    }
+
+    fn default_ai_builds() -> Vec<AiBuild> {
+        vec![AiBuild::default()]
+    }
+
+    fn default_games() -> u32 {
+        1
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let series: Self = toml::from_str(&std::fs::read_to_string(path)?)?;
+        anyhow::ensure!(!series.maps.is_empty(), "Series contains no maps");
+        Ok(series)
+    }
+
+    /// Every matchup of the series, with our own player set up as in `setup`.
+    pub fn matchups(&self, setup: &GameSetup) -> Vec<Matchup> {
+        let mut matchups = Vec::new();
+
+        for map in &self.maps {
+            for &opponent_race in &self.races {
+                for &difficulty in &self.difficulties {
+                    for &ai_build in &self.ai_builds {
+                        matchups.push(Matchup {
+                            map: map.clone(),
+                            setup: GameSetup {
+                                opponent: OpponentType::Computer,
+                                opponent_race,
+                                difficulty,
+                                ai_build,
+                                ..setup.clone()
+                            },
+                        });
+                    }
+                }
+            }
+        }
+
+        matchups
+    }
+}
+
+impl std::fmt::Display for Matchup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} vs {:?} {:?} ({:?})",
+            self.map, self.setup.opponent_race, self.setup.difficulty, self.setup.ai_build
+        )
+    }
+}
+
+/// Build a system collecting every [`GameResult`] of an app.
+pub fn collect_results(
+    results: Arc<Mutex<Vec<GameResult>>>,
+) -> impl FnMut(EventReader<GameResult>) {
+    move |mut events: EventReader<GameResult>| {
+        if let Ok(mut results) = results.lock() {
+            results.extend(events.read().copied());
+        }
+    }
+}
+
+#[derive(Serialize, Default, Clone, Debug, PartialEq)]
+pub struct Summary {
+    matchups: Vec<MatchupSummary>,
+    maps: Vec<MapSummary>,
+    games: Vec<GameRecord>,
+}
+
+/// Totals over a number of games.
+#[derive(Serialize, Default, Clone, Copy, Debug, PartialEq)]
+struct Tally {
+    games: usize,
+    victories: usize,
+    defeats: usize,
+    ties: usize,
+    win_rate: f32,
+    mean_game_loop: f32,
+    mean_score: f32,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct MatchupSummary {
+    map: String,
+    opponent_race: Race,
+    difficulty: Difficulty,
+    ai_build: AiBuild,
+    #[serde(flatten)]
+    tally: Tally,
+}
+
+/// Totals of every matchup played on a map.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct MapSummary {
+    map: String,
+    #[serde(flatten)]
+    tally: Tally,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct GameRecord {
+    map: String,
+    opponent_race: Race,
+    difficulty: Difficulty,
+    ai_build: AiBuild,
+    outcome: Outcome,
+    game_loop: u32,
+    score: i32,
+}
+
+impl Tally {
+    fn new(results: &[GameResult]) -> Self {
+        let count = |outcome| results.iter().filter(|r| r.outcome == outcome).count();
+        let mean = |value: fn(&GameResult) -> f32| {
+            results.iter().map(value).sum::<f32>() / results.len().max(1) as f32
+        };
+
+        Self {
+            games: results.len(),
+            victories: count(Outcome::Victory),
+            defeats: count(Outcome::Defeat),
+            ties: count(Outcome::Tie),
+            win_rate: count(Outcome::Victory) as f32 / results.len().max(1) as f32,
+            mean_game_loop: mean(|r| r.game_loop as f32),
+            mean_score: mean(|r| r.score as f32),
+        }
+    }
+
+    fn write_csv(&self, csv: &mut String) -> std::fmt::Result {
+        writeln!(
+            csv,
+            "{},{},{},{},{:.3},{:.1},{:.1}",
+            self.games,
+            self.victories,
+            self.defeats,
+            self.ties,
+            self.win_rate,
+            self.mean_game_loop,
+            self.mean_score,
+        )
+    }
+}
+
+impl GameRecord {
+    fn result(&self) -> GameResult {
+        GameResult {
+            outcome: self.outcome,
+            game_loop: self.game_loop,
+            score: self.score,
+        }
+    }
+}
+
+impl Summary {
+    /// Add the results of the games played of a matchup.
+    pub fn record(&mut self, matchup: &Matchup, results: &[GameResult]) {
+        let summary = MatchupSummary {
+            map: matchup.map.clone(),
+            opponent_race: matchup.setup.opponent_race,
+            difficulty: matchup.setup.difficulty,
+            ai_build: matchup.setup.ai_build,
+            tally: Tally::new(results),
+        };
+        info!(
+            "{matchup}: {}/{} won, {:.0}%",
+            summary.tally.victories,
+            summary.tally.games,
+            summary.tally.win_rate * 100.0
+        );
+        self.matchups.push(summary);
+
+        self.games.extend(results.iter().map(|result| GameRecord {
+            map: matchup.map.clone(),
+            opponent_race: matchup.setup.opponent_race,
+            difficulty: matchup.setup.difficulty,
+            ai_build: matchup.setup.ai_build,
+            outcome: result.outcome,
+            game_loop: result.game_loop,
+            score: result.score,
+        }));
+
+        let map_results = self
+            .games
+            .iter()
+            .filter(|game| game.map == matchup.map)
+            .map(GameRecord::result)
+            .collect::<Vec<_>>();
+        let tally = Tally::new(&map_results);
+
+        match self.maps.iter_mut().find(|map| map.map == matchup.map) {
+            Some(map) => map.tally = tally,
+            None => self.maps.push(MapSummary {
+                map: matchup.map.clone(),
+                tally,
+            }),
+        }
+    }
+
+    /// Write the summary as JSON if `path` has a `.json` extension, otherwise as CSV with a row per
+    /// matchup followed by a row per map, totalled over every opponent.
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let contents = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::to_string_pretty(self)?
+        } else {
+            self.to_csv()?
+        };
+
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    fn to_csv(&self) -> Result<String, std::fmt::Error> {
+        let mut csv = String::from(
+            "map,opponent_race,difficulty,ai_build,games,victories,defeats,ties,win_rate,mean_game_loop,mean_score\n",
+        );
+
+        for matchup in &self.matchups {
+            write!(
+                csv,
+                "{},{:?},{:?},{:?},",
+                csv_field(&matchup.map),
+                matchup.opponent_race,
+                matchup.difficulty,
+                matchup.ai_build,
+            )?;
+            matchup.tally.write_csv(&mut csv)?;
+        }
+
+        for map in &self.maps {
+            write!(csv, "{},all,all,all,", csv_field(&map.map))?;
+            map.tally.write_csv(&mut csv)?;
+        }
+
+        Ok(csv)
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}